@@ -0,0 +1,92 @@
+// SPDX-License-Identifier: MIT
+pragma solidity ^0.8.20;
+
+interface IERC20 {
+    function balanceOf(address account) external view returns (uint256);
+    function transfer(address to, uint256 amount) external returns (bool);
+    function transferFrom(address from, address to, uint256 amount) external returns (bool);
+}
+
+/// The L1 side of the rollup: it holds the bridged ETH and ERC-20 tokens, and pays withdrawals
+/// out against the batch roots the sequencer posts.
+/// Every withdrawal leaf commits to its asset (the zero address for ETH), so a claim from the
+/// batch of one asset can't be paid out in another.
+/// NOTE: leaves and proofs have to match WithdrawTree in rollup_core, and the events the ones
+/// the sequencer reads in bridge_lib.rs
+contract Bridge {
+    address public sequencer;
+    /// the root of each batch, by batch index (the sequencer's, across all assets)
+    bytes32[] public withdrawRoots;
+    /// the claimed withdrawals of each batch, 256 to a word
+    mapping(uint256 => mapping(uint256 => uint256)) public claimedBitMap;
+
+    event Deposit(address sender, uint256 amount);
+    event TokenDeposit(address sender, address token, uint256 amount);
+    event BatchPosted(uint256 withdrawRootIndex, bytes32 withdrawRoot);
+    event Withdraw(uint256 withdrawRootIndex, uint256 index, address account, address asset, uint256 amount);
+
+    error AlreadyClaimed();
+    error InvalidProof();
+    error NotSequencer();
+    error TransferFailed();
+
+    constructor() {
+        sequencer = msg.sender;
+    }
+
+    function deposit() external payable {
+        emit Deposit(msg.sender, msg.value);
+    }
+
+    /// Deposit `amount` of `token`, which has to be approved first. Only what the bridge
+    /// actually receives is credited (e.g. less for tokens that take a fee on transfers).
+    function depositToken(address token, uint256 amount) external {
+        uint256 balance = IERC20(token).balanceOf(address(this));
+        if (!IERC20(token).transferFrom(msg.sender, address(this), amount)) revert TransferFailed();
+        emit TokenDeposit(msg.sender, token, IERC20(token).balanceOf(address(this)) - balance);
+    }
+
+    function postBatch(bytes32 _withdrawRoot) external {
+        if (msg.sender != sequencer) revert NotSequencer();
+        emit BatchPosted(withdrawRoots.length, _withdrawRoot);
+        withdrawRoots.push(_withdrawRoot);
+    }
+
+    function isClaimed(uint256 withdrawRootIndex, uint256 index) public view returns (bool) {
+        uint256 mask = 1 << (index % 256);
+        return claimedBitMap[withdrawRootIndex][index / 256] & mask == mask;
+    }
+
+    function withdraw(
+        uint256 withdrawRootIndex,
+        uint256 index,
+        address account,
+        address asset,
+        uint256 amount,
+        bytes32[] calldata merkleProof
+    ) external {
+        if (isClaimed(withdrawRootIndex, index)) revert AlreadyClaimed();
+        bytes32 leaf = keccak256(abi.encodePacked(index, account, asset, amount));
+        if (!verify(merkleProof, withdrawRoots[withdrawRootIndex], leaf)) revert InvalidProof();
+        claimedBitMap[withdrawRootIndex][index / 256] |= 1 << (index % 256);
+
+        if (asset == address(0)) {
+            (bool sent,) = account.call{value: amount}("");
+            if (!sent) revert TransferFailed();
+        } else if (!IERC20(asset).transfer(account, amount)) {
+            revert TransferFailed();
+        }
+        emit Withdraw(withdrawRootIndex, index, account, asset, amount);
+    }
+
+    /// Hash the leaf with each proof element in turn (sorted pairs), as WithdrawTree does
+    function verify(bytes32[] calldata proof, bytes32 root, bytes32 leaf) internal pure returns (bool) {
+        bytes32 node = leaf;
+        for (uint256 i = 0; i < proof.length; i++) {
+            node = node < proof[i]
+                ? keccak256(abi.encodePacked(node, proof[i]))
+                : keccak256(abi.encodePacked(proof[i], node));
+        }
+        return node == root;
+    }
+}
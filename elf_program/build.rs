@@ -0,0 +1,26 @@
+//! Builds the program for the genesis in genesis.json, and records what it was built for (the
+//! rollup-core source and the genesis hash) in the program itself. The sequencer looks for it
+//! in the ELF it embeds, so an ELF built from anything else can't pass for this one.
+use rollup_core::engine::ChessState;
+use rollup_core::genesis::Genesis;
+
+fn main() {
+    println!("cargo:rerun-if-changed=../rollup_core/src");
+    println!("cargo:rerun-if-changed=../genesis.json");
+
+    let genesis: Genesis<ChessState> =
+        serde_json::from_slice(&std::fs::read("../genesis.json").unwrap())
+            .expect("bad genesis.json");
+    let hash = genesis.hash();
+    // the program itself commits to both, see rollup_core::zkvm
+    let out = std::path::Path::new(&std::env::var("OUT_DIR").unwrap()).join("genesis_hash.rs");
+    std::fs::write(
+        out,
+        format!(
+            "const GENESIS_HASH: [u8; 32] = {:?};\nconst BUILT_FOR: &str = {:?};\n",
+            hash.0,
+            rollup_core::built_for(hash)
+        ),
+    )
+    .unwrap();
+}
@@ -1,24 +1,12 @@
 #![no_main]
 sp1_zkvm::entrypoint!(main);
 
-mod engine;
-use engine::*;
-mod rollup_lib;
-use rollup_lib::*;
+// the genesis this program proves blocks from, and what it was built for, written by build.rs
+include!(concat!(env!("OUT_DIR"), "/genesis_hash.rs"));
 
 pub fn main() {
-    // read in the old state
-    let mut state = sp1_zkvm::io::read::<FullRollupState>();
-    // read in the next batch of transactions
-    let mem_pool = sp1_zkvm::io::read::<Vec<SignedTransaction<ChessTransactions>>>();
-
-    // execute each transaction
-    for tx in mem_pool.iter() {
-        state.execute(tx.clone()).unwrap();
-    }
-
-    // write the new state
-    sp1_zkvm::io::write(&state);
+    // replay the sealed blocks and commit to the new state root, see rollup_core::zkvm
+    rollup_core::zkvm::prove_blocks(GENESIS_HASH.into(), BUILT_FOR);
 
     // what happens next? For now, nothing!
     // This is only because we are currently running an authority rollup.
@@ -9,8 +9,7 @@ use tokio_tungstenite::{
 
 use kinode_lib::types::http_server::HttpServerAction;
 
-mod prover_types;
-use prover_types::ProveRequest;
+use rollup_core::prover_types::ProveRequest;
 
 type Receiver = mpsc::Receiver<Vec<u8>>;
 type Sender = mpsc::Sender<Vec<u8>>;
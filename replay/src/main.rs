@@ -0,0 +1,314 @@
+//! Audits a sequencer by replaying its transactions natively, with the same engine the
+//! elf_program proves: start from a saved state, re-execute every block (checking each against
+//! the header the sequencer sealed), and compare the result with another saved state.
+use alloy_primitives::{FixedBytes, U256};
+use clap::Parser;
+use rollup_core::accounts::account_key;
+use rollup_core::engine::{game_key, ChessEvents, ChessState, ChessTransactions, FullRollupState};
+use rollup_core::genesis::Genesis;
+use rollup_core::persistence::{set_l1_settings, Persistence};
+use rollup_core::rollup_lib::{
+    balance_key, nonce_key, state_key, ArchivedBlock, Block, ExecutionEngine, SignedTransaction,
+};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// The genesis the rollup starts from, unless replaying from another state
+const GENESIS: &str = include_str!("../../genesis.json");
+/// The program the sequencer proves blocks with, which records what it was built for
+const ELF_FILE: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/../elf_program/elf/riscv32im-succinct-zkvm-elf"
+);
+
+/// Rollup replay tool
+#[derive(Parser, Debug)]
+#[command(author, version, about)]
+struct Args {
+    /// The state to start from: a genesis file, or a state saved by the sequencer (with either
+    /// codec), e.g. the `base` file in its archive drive. Defaults to genesis.json
+    #[arg(long)]
+    genesis: Option<PathBuf>,
+    /// Sealed blocks to replay, in order: JSON lists of blocks, or of archived blocks (e.g. the
+    /// `blocks-*.json` files in the sequencer's archive drive)
+    #[arg(long)]
+    blocks: Vec<PathBuf>,
+    /// Transactions to execute into the open block after the blocks (e.g. a `sequenced` list),
+    /// as a JSON list
+    #[arg(long)]
+    sequenced: Option<PathBuf>,
+    /// A saved state to check the result against. Without --blocks or --sequenced, its own
+    /// blocks and sequenced transactions are replayed (so --genesis has to be the state its
+    /// first block was sealed on)
+    #[arg(long)]
+    expect: Option<PathBuf>,
+}
+
+/// Block files hold either plain blocks, or blocks archived with their receipts
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum BlockEntry {
+    Archived(ArchivedBlock<ChessTransactions, ChessEvents>),
+    Block(Block<ChessTransactions>),
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+
+    println!("engine       {}", rollup_core::SOURCE_HASH);
+    let genesis: Genesis<ChessState> = serde_json::from_str(GENESIS)?;
+    let built_for = rollup_core::built_for(genesis.hash());
+    match std::fs::read(ELF_FILE) {
+        Ok(elf)
+            if !elf
+                .windows(built_for.len())
+                .any(|bytes| bytes == built_for.as_bytes()) =>
+        {
+            println!(
+                "warning: the elf_program was built from a different rollup-core or genesis.json, \
+                 so this is not the engine it proves"
+            )
+        }
+        Ok(_) => {}
+        Err(_) => println!("warning: the elf_program hasn't been built, can't check its engine"),
+    }
+    replay(&args, &genesis)
+}
+
+/// Replay what `args` asks for on top of its starting state (or `genesis`), and check the result
+/// against the expected state if there is one
+fn replay(args: &Args, genesis: &Genesis<ChessState>) -> anyhow::Result<()> {
+    // saved state from before genesis files settles where genesis.json says
+    set_l1_settings(genesis.l1_chain_id, genesis.bridge);
+    let mut state = match args.genesis {
+        Some(ref path) => read_genesis(path)?,
+        None => FullRollupState::from_genesis(genesis),
+    };
+    let expected = match args.expect {
+        Some(ref path) => Some(read_state(path)?),
+        None => None,
+    };
+
+    let (blocks, sequenced) = match expected {
+        Some(ref expected) if args.blocks.is_empty() && args.sequenced.is_none() => {
+            (expected.blocks.clone(), expected.sequenced.clone())
+        }
+        _ => {
+            let mut blocks = vec![];
+            for path in args.blocks.iter() {
+                let entries: Vec<BlockEntry> = serde_json::from_slice(&std::fs::read(path)?)
+                    .map_err(|e| anyhow::anyhow!("reading blocks from {}: {e}", path.display()))?;
+                blocks.extend(entries.into_iter().map(|entry| match entry {
+                    BlockEntry::Archived(archived) => archived.block,
+                    BlockEntry::Block(block) => block,
+                }));
+            }
+            let sequenced: Vec<SignedTransaction<ChessTransactions>> = match args.sequenced {
+                Some(ref path) => serde_json::from_slice(&std::fs::read(path)?).map_err(|e| {
+                    anyhow::anyhow!("reading transactions from {}: {e}", path.display())
+                })?,
+                None => vec![],
+            };
+            (blocks, sequenced)
+        }
+    };
+
+    for block in blocks.iter() {
+        state
+            .replay_block(block)
+            .map_err(|e| anyhow::anyhow!("replaying block {}: {e}", block.header.number))?;
+    }
+    // the open block's transactions ran at the time it was opened at, if we know it
+    if let Some(ref expected) = expected {
+        state.timestamp = expected.timestamp;
+    }
+    for (index, tx) in sequenced.into_iter().enumerate() {
+        let hash = tx.hash();
+        state
+            .execute(tx)
+            .map_err(|e| anyhow::anyhow!("open block, transaction {index} ({hash}) failed: {e}"))?;
+    }
+
+    println!("blocks       {} replayed", blocks.len());
+    println!(
+        "open block   {}, with {} transactions",
+        state.block_number(),
+        state.sequenced.len()
+    );
+    println!("state root   {}", state.state_root());
+    if let Some(header) = state.latest_header() {
+        println!("latest block {} ({})", header.number, header.hash());
+    }
+
+    let Some(expected) = expected else {
+        return Ok(());
+    };
+    let differences = compare(&state, &expected);
+    if differences.is_empty() {
+        println!("matches the expected state");
+        return Ok(());
+    }
+    for difference in differences.iter() {
+        println!("  {difference}");
+    }
+    Err(anyhow::anyhow!(
+        "replayed state differs from the expected state in {} places",
+        differences.len()
+    ))
+}
+
+fn read_genesis(path: &PathBuf) -> anyhow::Result<FullRollupState> {
+    let bytes = std::fs::read(path)?;
+    match serde_json::from_slice::<Genesis<ChessState>>(&bytes) {
+        Ok(genesis) => Ok(FullRollupState::from_genesis(&genesis)),
+        Err(_) => read_state(path),
+    }
+}
+
+fn read_state(path: &PathBuf) -> anyhow::Result<FullRollupState> {
+    FullRollupState::decode(&std::fs::read(path)?)
+        .map_err(|e| anyhow::anyhow!("reading state from {}: {e}", path.display()))
+}
+
+/// Everything the replayed state commits to differently from the expected one
+fn compare(replayed: &FullRollupState, expected: &FullRollupState) -> Vec<String> {
+    let mut differences = vec![];
+    if replayed.block_number() != expected.block_number() {
+        differences.push(format!(
+            "open block is {}, expected {}",
+            replayed.block_number(),
+            expected.block_number()
+        ));
+    }
+    if replayed.sequenced.len() != expected.sequenced.len() {
+        differences.push(format!(
+            "open block has {} transactions, expected {}",
+            replayed.sequenced.len(),
+            expected.sequenced.len()
+        ));
+    }
+    let latest = |state: &FullRollupState| state.latest_header().map(|header| header.hash());
+    if latest(replayed) != latest(expected) {
+        differences.push(format!(
+            "latest block hash is {:?}, expected {:?}",
+            latest(replayed),
+            latest(expected)
+        ));
+    }
+
+    let (replayed_tree, expected_tree) = (replayed.state_tree(), expected.state_tree());
+    if replayed_tree.root() == expected_tree.root() {
+        return differences;
+    }
+    let labels = leaf_labels(&[replayed, expected]);
+    let keys: Vec<&FixedBytes<32>> = replayed_tree
+        .leaves()
+        .keys()
+        .chain(expected_tree.leaves().keys())
+        .collect::<std::collections::BTreeSet<_>>()
+        .into_iter()
+        .collect();
+    for key in keys {
+        let (a, b) = (replayed_tree.get(key), expected_tree.get(key));
+        if a != b {
+            let label = labels.get(key).cloned().unwrap_or(key.to_string());
+            differences.push(format!(
+                "{label} is {}, expected {}",
+                a.map(|v| v.to_string()).unwrap_or("absent".to_string()),
+                b.map(|v| v.to_string()).unwrap_or("absent".to_string())
+            ));
+        }
+    }
+    differences
+}
+
+/// Names for the state tree leaves that are easy to tell apart (balances, nonces, withdrawal
+/// roots, policy accounts, games, the rollup parameters and the genesis); any other leaf is only
+/// shown by its key
+fn leaf_labels(states: &[&FullRollupState]) -> BTreeMap<FixedBytes<32>, String> {
+    let mut labels = BTreeMap::from([
+        (state_key("l1_block", &[]), "l1 block".to_string()),
+        (state_key("fee_per_gas", &[]), "fee per gas".to_string()),
+        (state_key("fee_recipient", &[]), "fee recipient".to_string()),
+        (state_key("genesis", &[]), "genesis hash".to_string()),
+    ]);
+    for state in states {
+        for (asset, accounts) in state.balances.iter() {
+            for account in accounts.keys() {
+                labels.insert(
+                    balance_key(asset, account),
+                    format!("balance of {account} in {asset}"),
+                );
+            }
+        }
+        for account in state.nonces.keys() {
+            labels.insert(nonce_key(account), format!("nonce of {account}"));
+        }
+        for asset in state.withdraw_trees.keys() {
+            labels.insert(
+                state_key("withdraw_root", &[asset.as_slice()]),
+                format!("pending withdrawals root of {asset}"),
+            );
+        }
+        for index in 0..state.batches.len() {
+            labels.insert(
+                state_key("batch", &[&U256::from(index).to_be_bytes::<32>()]),
+                format!("root of batch {index}"),
+            );
+        }
+        for account in state.accounts.keys() {
+            labels.insert(account_key(account), format!("policy account {account}"));
+        }
+        for game_id in state.state.games.keys() {
+            labels.insert(game_key(game_id), format!("game {game_id}"));
+        }
+    }
+    labels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A genesis, and the state a sequencer saved (with the Json codec) after two blocks and a
+    /// transaction from it. It has to be saved again whenever the engine changes how it seals.
+    const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures");
+    const STATE: &str = include_str!("../tests/fixtures/state.json");
+
+    fn replay_to(expect: &std::path::Path) -> anyhow::Result<()> {
+        let args = Args::parse_from([
+            "replay".as_ref(),
+            "--genesis".as_ref(),
+            format!("{FIXTURES}/genesis.json").as_ref(),
+            "--expect".as_ref(),
+            expect.as_os_str(),
+        ]);
+        replay(&args, &serde_json::from_str(GENESIS)?)
+    }
+
+    #[test]
+    fn replays_to_the_expected_state() {
+        replay_to(format!("{FIXTURES}/state.json").as_ref()).unwrap();
+    }
+
+    #[test]
+    fn fails_when_the_expected_state_differs() {
+        // a balance the sequencer would have gotten wrong
+        let mut saved: serde_json::Value = serde_json::from_str(STATE).unwrap();
+        let balance = &mut saved["state"]["balances"]["0x0000000000000000000000000000000000000000"]
+            ["0x3325a78425f17a7e487eb5666b2bfd93abb06c70"];
+        assert_eq!(balance, "0x14");
+        *balance = "0x15".into();
+        let path = std::env::temp_dir().join(format!("replay-differs-{}.json", std::process::id()));
+        std::fs::write(&path, saved.to_string()).unwrap();
+
+        let result = replay_to(&path);
+        std::fs::remove_file(&path).unwrap();
+        let error = result.unwrap_err().to_string();
+        assert!(
+            error.contains("differs from the expected state in 1 places"),
+            "{error}"
+        );
+    }
+}
@@ -0,0 +1,44 @@
+//! Fingerprints the engine source, so that the sequencer can refuse to embed an ELF that was
+//! built from a different version of it.
+use std::{env, fs, path::Path};
+
+fn main() {
+    let src = Path::new(&env::var("CARGO_MANIFEST_DIR").unwrap()).join("src");
+    println!("cargo:rerun-if-changed={}", src.display());
+
+    let mut files = Vec::new();
+    collect(&src, &mut files);
+    files.sort();
+
+    // FNV-1a over every file's relative path and contents, in a stable order
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for file in files {
+        let name = file
+            .strip_prefix(&src)
+            .unwrap()
+            .to_string_lossy()
+            .replace('\\', "/");
+        for byte in name.bytes().chain(fs::read(&file).unwrap()) {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    let out = Path::new(&env::var("OUT_DIR").unwrap()).join("source_hash.rs");
+    fs::write(
+        out,
+        format!("pub const SOURCE_HASH: &str = \"{hash:016x}\";\n"),
+    )
+    .unwrap();
+}
+
+fn collect(dir: &Path, files: &mut Vec<std::path::PathBuf>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            collect(&path, files);
+        } else {
+            files.push(path);
+        }
+    }
+}
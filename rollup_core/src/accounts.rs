@@ -0,0 +1,408 @@
+use crate::rollup_lib::{
+    state_key, Asset, BaseRollupState, Event, RollupError, SignedTransaction, Transaction,
+};
+use alloy_primitives::{keccak256, Address as AlloyAddress, FixedBytes, U256};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+/// Spending limits reset every day (of block timestamps)
+pub const DAY: u64 = 24 * 60 * 60;
+
+/// A policy account has no key of its own: its transactions are validated by its policy instead
+/// of by the signature of its address (see BaseRollupState::authorize). Accounts are created by
+/// TransactionData::CreateAccount, e.g. for a chess club or a treasury that several people share.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PolicyAccount {
+    pub policy: AccountPolicy,
+    /// what the account spent on `day`, under a SpendingLimit
+    #[serde(default)]
+    pub spent: U256,
+    #[serde(default)]
+    pub day: u64,
+    /// the recovery the guardian started, under a Guardian policy
+    #[serde(default)]
+    pub recovery: Option<Recovery>,
+}
+
+/// Who may sign for a policy account, and what they may do
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum AccountPolicy {
+    /// at least `threshold` of the `owners` have to sign every transaction
+    Multisig {
+        owners: Vec<AlloyAddress>,
+        threshold: usize,
+    },
+    /// the owner signs alone, but the account can't spend more than `daily_limit` of `asset` a
+    /// day. Spending is what the account's balance went down by over each transaction (fees
+    /// included), plus whatever it approves others to spend.
+    SpendingLimit {
+        owner: AlloyAddress,
+        asset: Asset,
+        daily_limit: U256,
+    },
+    /// the owner signs alone, and if they lose their key, the guardian can hand the account to
+    /// a new owner: `delay` seconds after starting a recovery, unless the owner cancels it
+    Guardian {
+        owner: AlloyAddress,
+        guardian: AlloyAddress,
+        delay: u64,
+    },
+}
+
+/// A guardian recovery in progress: the account goes to `owner` from timestamp `after` on
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Recovery {
+    pub owner: AlloyAddress,
+    pub after: u64,
+}
+
+/// What the signers of a policy account's transaction sign (with personal_sign, over its JSON).
+/// It names the account, so the signatures can't be replayed on another account with the same
+/// owners.
+#[derive(Serialize)]
+struct AccountMessage<'a, T> {
+    account: AlloyAddress,
+    tx: &'a Transaction<T>,
+}
+
+pub fn account_key(account: &AlloyAddress) -> FixedBytes<32> {
+    state_key("account", &[account.as_slice()])
+}
+
+impl AccountPolicy {
+    /// Checks that the policy can ever be satisfied
+    pub fn validate(&self) -> Result<(), RollupError> {
+        match self {
+            AccountPolicy::Multisig { owners, threshold } => {
+                if owners.iter().collect::<BTreeSet<_>>().len() != owners.len() {
+                    return Err(RollupError::InvalidPolicy("duplicate owners".to_string()));
+                }
+                if *threshold == 0 || *threshold > owners.len() {
+                    return Err(RollupError::InvalidPolicy(format!(
+                        "threshold {threshold} of {} owners",
+                        owners.len()
+                    )));
+                }
+            }
+            AccountPolicy::SpendingLimit { .. } => {}
+            AccountPolicy::Guardian {
+                owner, guardian, ..
+            } => {
+                if owner == guardian {
+                    return Err(RollupError::InvalidPolicy(
+                        "the owner can't be their own guardian".to_string(),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that an account with this policy may switch to `policy` with SetPolicy, which only
+    /// its owner signs unless it is a Multisig. So that a stolen owner key can't undo what guards
+    /// the account, a spending limit can only be lowered, and the guardian stays (with at least
+    /// the same delay) while the owner may change. The guardian is there for a lost key, though:
+    /// whoever holds the owner key can still spend, and cancel recoveries.
+    pub fn may_change_to(&self, policy: &AccountPolicy) -> Result<(), RollupError> {
+        match (self, policy) {
+            (AccountPolicy::Multisig { .. }, _) => Ok(()),
+            (
+                AccountPolicy::SpendingLimit {
+                    owner,
+                    asset,
+                    daily_limit,
+                },
+                AccountPolicy::SpendingLimit {
+                    owner: new_owner,
+                    asset: new_asset,
+                    daily_limit: new_limit,
+                },
+            ) if new_owner == owner && new_asset == asset && new_limit <= daily_limit => Ok(()),
+            (AccountPolicy::SpendingLimit { .. }, _) => Err(RollupError::InvalidPolicy(
+                "a spending limit can only be lowered".to_string(),
+            )),
+            (
+                AccountPolicy::Guardian {
+                    guardian, delay, ..
+                },
+                AccountPolicy::Guardian {
+                    guardian: new_guardian,
+                    delay: new_delay,
+                    ..
+                },
+            ) if new_guardian == guardian && new_delay >= delay => Ok(()),
+            (AccountPolicy::Guardian { .. }, _) => Err(RollupError::InvalidPolicy(
+                "the guardian can't be removed, nor its delay shortened".to_string(),
+            )),
+        }
+    }
+
+    /// Who controls an account with this policy: the keys that sign for it, and how many of them
+    /// have to
+    pub fn control(&self) -> (BTreeSet<AlloyAddress>, usize) {
+        match self {
+            AccountPolicy::Multisig { owners, threshold } => {
+                (owners.iter().copied().collect(), *threshold)
+            }
+            AccountPolicy::SpendingLimit { owner, .. } | AccountPolicy::Guardian { owner, .. } => {
+                (BTreeSet::from([*owner]), 1)
+            }
+        }
+    }
+
+    /// Checks that `signers` (all distinct) are enough to sign for the account
+    pub fn authorize(&self, signers: &[AlloyAddress]) -> Result<(), RollupError> {
+        match self {
+            AccountPolicy::Multisig { owners, threshold } => {
+                if signers.iter().any(|signer| !owners.contains(signer)) {
+                    return Err(RollupError::BadSignature);
+                }
+                if signers.len() < *threshold {
+                    return Err(RollupError::NotEnoughSignatures {
+                        needed: *threshold,
+                        got: signers.len(),
+                    });
+                }
+                Ok(())
+            }
+            AccountPolicy::SpendingLimit { owner, .. } | AccountPolicy::Guardian { owner, .. } => {
+                match signers {
+                    [signer] if signer == owner => Ok(()),
+                    _ => Err(RollupError::BadSignature),
+                }
+            }
+        }
+    }
+}
+
+impl<T: Serialize> SignedTransaction<T> {
+    /// The (distinct) addresses that signed for a policy account: `sig` and every cosignature,
+    /// each over the AccountMessage
+    pub fn account_signers(&self) -> Result<Vec<AlloyAddress>, RollupError> {
+        let message = serde_json::to_string(&AccountMessage {
+            account: self.pub_key,
+            tx: &self.tx,
+        })
+        .unwrap();
+        let mut signers = vec![];
+        for sig in std::iter::once(&self.sig).chain(self.cosigs.iter()) {
+            let signer = sig
+                .recover_address_from_msg(message.as_bytes())
+                .map_err(|_| RollupError::BadSignature)?;
+            if !signers.contains(&signer) {
+                signers.push(signer);
+            }
+        }
+        Ok(signers)
+    }
+}
+
+impl<S, T, E> BaseRollupState<S, T, E> {
+    /// The address of the account `creator` creates with `salt` and `policy`, as CREATE2 would
+    /// derive it (with the policy JSON in place of the init code)
+    pub fn account_address(
+        creator: &AlloyAddress,
+        salt: U256,
+        policy: &AccountPolicy,
+    ) -> AlloyAddress {
+        creator.create2(
+            salt.to_be_bytes::<32>(),
+            keccak256(serde_json::to_vec(policy).unwrap()),
+        )
+    }
+
+    /// How much more of `asset` the account may spend today, if it has a spending limit on it
+    pub fn spending_allowed(&self, account: &AlloyAddress) -> Option<(Asset, U256)> {
+        let account = self.accounts.get(account)?;
+        let AccountPolicy::SpendingLimit {
+            asset, daily_limit, ..
+        } = account.policy
+        else {
+            return None;
+        };
+        let spent = if account.day == self.timestamp / DAY {
+            account.spent
+        } else {
+            U256::ZERO
+        };
+        Some((asset, daily_limit.saturating_sub(spent)))
+    }
+
+    /// Check what `account` spent of `asset` over a transaction that emitted `events` (given its
+    /// `balance` before it) against the `allowed` rest of its spending limit, and count it
+    pub fn spend_within_limit(
+        &mut self,
+        account: &AlloyAddress,
+        asset: Asset,
+        allowed: U256,
+        balance: U256,
+        events: &[Event<E>],
+    ) -> Result<(), RollupError> {
+        let approved = events
+            .iter()
+            .filter_map(|event| match event {
+                Event::Approval {
+                    asset: approved,
+                    owner,
+                    amount,
+                    ..
+                } if *approved == asset && owner == account => Some(*amount),
+                _ => None,
+            })
+            .fold(U256::ZERO, |sum, amount| sum.saturating_add(amount));
+        let spent = balance
+            .saturating_sub(self.balance_of(&asset, account))
+            .saturating_add(approved);
+        if spent > allowed {
+            return Err(RollupError::SpendingLimitExceeded {
+                asset,
+                allowed,
+                spent,
+            });
+        }
+        let today = self.timestamp / DAY;
+        if let Some(account) = self.accounts.get_mut(account) {
+            if account.day != today {
+                account.day = today;
+                account.spent = U256::ZERO;
+            }
+            account.spent = account.spent.saturating_add(spent);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::FullRollupState;
+    use crate::rollup_lib::{ExecutionEngine, TransactionData, GAS_TOKEN};
+    use crate::testing::*;
+
+    fn multisig(owners: &[u8], threshold: usize) -> AccountPolicy {
+        AccountPolicy::Multisig {
+            owners: owners.iter().map(|n| address(*n)).collect(),
+            threshold,
+        }
+    }
+
+    #[test]
+    fn multisig_thresholds_are_inclusive() {
+        assert!(multisig(&[1, 2, 3], 0).validate().is_err());
+        assert!(multisig(&[1, 2, 3], 3).validate().is_ok());
+        assert!(multisig(&[1, 2, 3], 4).validate().is_err());
+
+        let policy = multisig(&[1, 2, 3], 2);
+        assert_eq!(
+            policy.authorize(&[address(1)]),
+            Err(RollupError::NotEnoughSignatures { needed: 2, got: 1 })
+        );
+        assert_eq!(policy.authorize(&[address(1), address(2)]), Ok(()));
+        assert_eq!(
+            policy.authorize(&[address(1), address(2), address(3)]),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn a_cosigner_counts_once_however_often_they_sign() {
+        let account = AlloyAddress::repeat_byte(7);
+        let tx = transaction(0, TransactionData::CancelRecovery);
+        let twice = sign_for(account, &[1, 1, 1], tx);
+        assert_eq!(twice.account_signers(), Ok(vec![address(1)]));
+        assert_eq!(
+            multisig(&[1, 2, 3], 2).authorize(&twice.account_signers().unwrap()),
+            Err(RollupError::NotEnoughSignatures { needed: 2, got: 1 })
+        );
+        assert!(multisig(&[1, 1, 2], 2).validate().is_err());
+    }
+
+    #[test]
+    fn spending_limits_roll_over_at_midnight() {
+        let mut state = FullRollupState::default();
+        let account = AlloyAddress::repeat_byte(7);
+        let limit = U256::from(50);
+        state.accounts.insert(
+            account,
+            PolicyAccount {
+                policy: AccountPolicy::SpendingLimit {
+                    owner: address(1),
+                    asset: GAS_TOKEN,
+                    daily_limit: limit,
+                },
+                spent: U256::ZERO,
+                day: 0,
+                recovery: None,
+            },
+        );
+        deposit(&mut state, account, 100);
+        let spend = |state: &mut FullRollupState, amount: u64| {
+            let (asset, allowed) = state.spending_allowed(&account).unwrap();
+            let balance = state.balance_of(&asset, &account);
+            state.debit(asset, account, U256::from(amount)).unwrap();
+            state.spend_within_limit(&account, asset, allowed, balance, &[])
+        };
+
+        state.timestamp = DAY - 1;
+        spend(&mut state, 50).unwrap();
+        assert_eq!(
+            state.spending_allowed(&account),
+            Some((GAS_TOKEN, U256::ZERO))
+        );
+        // the last second of the day is still the same day
+        assert!(spend(&mut state, 1).is_err());
+        state.timestamp = DAY;
+        assert_eq!(state.spending_allowed(&account), Some((GAS_TOKEN, limit)));
+        spend(&mut state, 20).unwrap();
+        assert_eq!(state.accounts[&account].day, 1);
+        assert_eq!(state.accounts[&account].spent, U256::from(20));
+    }
+
+    #[test]
+    fn recoveries_finish_once_the_delay_has_passed() {
+        let mut state = FullRollupState::default();
+        let policy = AccountPolicy::Guardian {
+            owner: address(1),
+            guardian: address(5),
+            delay: 100,
+        };
+        let create = TransactionData::CreateAccount {
+            policy: policy.clone(),
+            salt: U256::ZERO,
+        };
+        state.execute(sign(1, transaction(0, create))).unwrap();
+        let account = FullRollupState::account_address(&address(1), U256::ZERO, &policy);
+        let finish = |nonce| {
+            sign(
+                5,
+                transaction(nonce, TransactionData::FinishRecovery(account)),
+            )
+        };
+
+        state.timestamp = 1_000;
+        let start = TransactionData::StartRecovery {
+            account,
+            owner: address(6),
+        };
+        state.execute(sign(5, transaction(0, start))).unwrap();
+        assert_eq!(
+            state.accounts[&account].recovery,
+            Some(Recovery {
+                owner: address(6),
+                after: 1_100,
+            })
+        );
+        state.timestamp = 1_099;
+        assert_eq!(
+            state.execute(finish(1)),
+            Err(RollupError::RecoveryNotReady(1_100))
+        );
+        state.timestamp = 1_100;
+        state.execute(finish(1)).unwrap();
+        assert_eq!(
+            state.accounts[&account].policy.control().0,
+            [address(6)].into()
+        );
+        assert_eq!(state.accounts[&account].recovery, None);
+    }
+}
@@ -0,0 +1,1398 @@
+use crate::accounts::{AccountPolicy, PolicyAccount, Recovery};
+use crate::rollup_lib::{
+    state_key, Action, Asset, BaseRollupState, Event, ExecutionEngine, RollupError,
+    ScopedExtension, SessionKey, SignedTransaction, StateCommitment, TransactionData,
+};
+use alloy_primitives::{keccak256, Address as AlloyAddress, FixedBytes, U256};
+use chess::{Board, BoardStatus, ChessMove};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::str::FromStr;
+
+pub type GameId = U256;
+
+/// A game of chess
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Game {
+    turns: u64,
+    board: String,
+    white: AlloyAddress,
+    black: AlloyAddress,
+    #[serde(default)]
+    asset: Asset,
+    wager: U256,
+    status: String, // TODO should be an enum: "<Address> won", "stalemate", "active"
+}
+
+/// A game of chess that has been proposed by white, but not accepted by black yet
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PendingGame {
+    white: AlloyAddress,
+    black: AlloyAddress,
+    accepted: (bool, bool),
+    #[serde(default)]
+    asset: Asset,
+    wager: U256,
+}
+
+/// While BaseRollupState contains all the of the state that any chain will need to get started,
+/// like balances, withdrawals, etc. ChessState contains all of the state that is specific to the
+/// chess rollup
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ChessState {
+    pub next_game_id: GameId,
+    pub pending_games: BTreeMap<GameId, PendingGame>,
+    pub games: BTreeMap<GameId, Game>,
+}
+
+/// Every game (pending or not) is a leaf of the state tree, keyed by its id
+impl StateCommitment for ChessState {
+    fn leaves(&self) -> Vec<(FixedBytes<32>, FixedBytes<32>)> {
+        let mut leaves = vec![(
+            state_key("next_game_id", &[]),
+            FixedBytes::from(self.next_game_id),
+        )];
+        for (game_id, pending_game) in self.pending_games.iter() {
+            leaves.push((
+                pending_game_key(game_id),
+                keccak256(serde_json::to_vec(pending_game).unwrap()),
+            ));
+        }
+        for (game_id, game) in self.games.iter() {
+            leaves.push((
+                game_key(game_id),
+                keccak256(serde_json::to_vec(game).unwrap()),
+            ));
+        }
+        leaves
+    }
+}
+
+pub fn game_key(game_id: &GameId) -> FixedBytes<32> {
+    state_key("game", &[&game_id.to_be_bytes::<32>()])
+}
+
+pub fn pending_game_key(game_id: &GameId) -> FixedBytes<32> {
+    state_key("pending_game", &[&game_id.to_be_bytes::<32>()])
+}
+
+/// All of the transactions that will go in the TransactionData::Extension variant
+/// that we need for different actions in chess
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum ChessTransactions {
+    ProposeGame {
+        white: AlloyAddress,
+        black: AlloyAddress,
+        asset: Asset,
+        wager: U256,
+    },
+    StartGame(GameId),
+    Move {
+        game_id: GameId,
+        san: String,
+    },
+    Resign(GameId),
+}
+
+/// All of the events that will go in the Event::Extension variant, telling indexers and
+/// the UI what happened in a chess transaction
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum ChessEvents {
+    GameProposed {
+        game_id: GameId,
+        white: AlloyAddress,
+        black: AlloyAddress,
+        asset: Asset,
+        wager: U256,
+    },
+    GameStarted(GameId),
+    MoveMade {
+        game_id: GameId,
+        player: AlloyAddress,
+        san: String,
+    },
+    /// `winner` is None for a stalemate
+    GameEnded {
+        game_id: GameId,
+        winner: Option<AlloyAddress>,
+    },
+}
+
+/// ChessState, ChessTransactions and ChessEvents help to extend the "basic" rollup state
+/// This is the core thing that you need to extend to modify this rollup: either
+/// changing the ChessState, or changing the ChessTransactions, and making sure that
+/// they are pluggable with BaseRollupState.
+pub type FullRollupState = BaseRollupState<ChessState, ChessTransactions, ChessEvents>;
+
+impl Default for FullRollupState {
+    fn default() -> Self {
+        Self {
+            blocks: vec![],
+            archived: None,
+            sequenced: vec![],
+            receipts: BTreeMap::new(),
+            timestamp: 0,
+            state_trees: VecDeque::new(),
+            balances: BTreeMap::new(),
+            nonces: BTreeMap::new(),
+            allowances: BTreeMap::new(),
+            session_keys: BTreeMap::new(),
+            accounts: BTreeMap::new(),
+            withdrawals: vec![],
+            withdraw_trees: BTreeMap::new(),
+            withdrawal_records: BTreeMap::new(),
+            batches: vec![],
+            l1_block: U256::ZERO,
+            fee_per_gas: U256::ZERO,
+            fee_recipient: AlloyAddress::ZERO,
+            genesis_hash: FixedBytes::ZERO,
+            l1_chain_id: 0,
+            bridge: AlloyAddress::ZERO,
+            state: ChessState {
+                next_game_id: U256::ZERO,
+                pending_games: BTreeMap::new(),
+                games: BTreeMap::new(),
+            },
+        }
+    }
+}
+
+/// This is where all of the business logic for the chess rollup lives.
+/// The `execute` function is called by the sequencer to process a single transaction.
+/// Every check happens before the state is touched (and a failed batch is rolled back), so a
+/// rejected transaction leaves no trace.
+impl ExecutionEngine<ChessTransactions> for FullRollupState {
+    // process a single transaction
+    fn execute(&mut self, stx: SignedTransaction<ChessTransactions>) -> Result<(), RollupError> {
+        // DO NOT verify a signature for a bridge transaction
+        if let TransactionData::BridgeTokens {
+            asset,
+            amount,
+            block,
+        } = stx.tx.data
+        {
+            self.credit(asset, stx.pub_key, amount)?;
+            self.l1_block = block;
+            let account = stx.pub_key;
+            self.record(
+                stx,
+                vec![Event::Deposit {
+                    asset,
+                    account,
+                    amount,
+                }],
+            );
+            return Ok(());
+        }
+
+        // nor for sealing withdrawals, which is up to the sequencer. Its nonce is the index of
+        // the first new batch, so that every seal has a hash of its own.
+        if let TransactionData::SealWithdrawals = stx.tx.data {
+            let expected = U256::from(self.batches.len());
+            if stx.tx.nonce != expected {
+                return Err(RollupError::BadNonce {
+                    expected,
+                    got: stx.tx.nonce,
+                });
+            }
+            if self.withdraw_trees.is_empty() {
+                return Err(RollupError::NoWithdrawals);
+            }
+            let events = self
+                .seal_withdrawals()
+                .map(|batch| Event::BatchSealed {
+                    asset: self.batches[batch].asset,
+                    batch,
+                    root: self.batches[batch].root,
+                })
+                .collect();
+            self.record(stx, events);
+            return Ok(());
+        }
+
+        // a transaction past its deadline is refused no matter what, see Transaction::valid_until
+        self.check_valid_until(&stx.tx)?;
+
+        let expected = self.nonce_of(&stx.pub_key);
+        if stx.tx.nonce != expected {
+            return Err(RollupError::BadNonce {
+                expected,
+                got: stx.tx.nonce,
+            });
+        }
+
+        // verify the signature, which may come from one of the account's session keys
+        let session = self.authorize(&stx)?;
+        stx.verify_sponsorship()?;
+
+        // the fee is only known once the transaction has run, and so is what a policy account
+        // with a spending limit spent: if either is too much, the transaction has to be undone
+        let limit = self
+            .spending_allowed(&stx.pub_key)
+            .map(|(asset, allowed)| (asset, allowed, self.balance_of(&asset, &stx.pub_key)));
+        let snapshot = if self.fee_per_gas.is_zero() && limit.is_none() {
+            None
+        } else {
+            Some(self.snapshot())
+        };
+        let mut events = self.apply(stx.pub_key, stx.tx.data.clone(), session)?;
+        let charged = match self.charge_fee(&stx, &events) {
+            Ok(fee) => {
+                events.extend(fee);
+                match limit {
+                    Some((asset, allowed, balance)) => {
+                        self.spend_within_limit(&stx.pub_key, asset, allowed, balance, &events)
+                    }
+                    None => Ok(()),
+                }
+            }
+            Err(e) => Err(e),
+        };
+        if let Err(e) = charged {
+            if let Some(snapshot) = snapshot {
+                self.restore(snapshot);
+            }
+            return Err(e);
+        }
+
+        self.nonces.insert(stx.pub_key, expected + U256::from(1));
+        self.record(stx, events);
+        Ok(())
+    }
+}
+
+impl FullRollupState {
+    /// Apply a single action on behalf of `sender`, returning the events it emitted.
+    /// If it was signed by `session` key, it has to be in the key's scope, and counts towards
+    /// what the key spent once it succeeds.
+    fn apply(
+        &mut self,
+        sender: AlloyAddress,
+        data: TransactionData<ChessTransactions>,
+        session: Option<AlloyAddress>,
+    ) -> Result<Vec<Event<ChessEvents>>, RollupError> {
+        let spends = match session {
+            Some(key) => Some((key, self.session_spend(&key, &data)?)),
+            None => None,
+        };
+        let events = match data {
+            // deposits only come from the bridge and seals from the sequencer, never in a batch
+            TransactionData::BridgeTokens { .. } | TransactionData::SealWithdrawals => {
+                return Err(RollupError::Unauthorized)
+            }
+            TransactionData::WithdrawTokens { asset, amount } => {
+                self.debit(asset, sender, amount)?;
+                if let Err(e) = self.withdraw(asset, sender, amount) {
+                    // undo the debit so the rejected transaction leaves no trace
+                    self.credit(asset, sender, amount)?;
+                    return Err(e);
+                }
+                vec![Event::Withdrawal {
+                    asset,
+                    account: sender,
+                    amount,
+                }]
+            }
+            TransactionData::Transfer {
+                asset,
+                from,
+                to,
+                amount,
+            } => {
+                // only the owner can move their own funds, see TransferFrom for everyone else
+                if from != sender {
+                    return Err(RollupError::Unauthorized);
+                }
+                self.debit(asset, from, amount)?;
+                self.credit(asset, to, amount)?;
+                vec![Event::Transfer {
+                    asset,
+                    from,
+                    to,
+                    amount,
+                }]
+            }
+            TransactionData::Approve {
+                asset,
+                spender,
+                amount,
+            } => {
+                self.approve(asset, sender, spender, amount);
+                vec![Event::Approval {
+                    asset,
+                    owner: sender,
+                    spender,
+                    amount,
+                }]
+            }
+            TransactionData::TransferFrom {
+                asset,
+                from,
+                to,
+                amount,
+            } => {
+                // check everything before spending the allowance, so a failure leaves no trace
+                let available = self.balance_of(&asset, &from);
+                if available < amount {
+                    return Err(RollupError::InsufficientFunds {
+                        asset,
+                        account: from,
+                        needed: amount,
+                        available,
+                    });
+                }
+                if from != to && self.balance_of(&asset, &to).checked_add(amount).is_none() {
+                    return Err(RollupError::BalanceOverflow(to));
+                }
+                self.spend_allowance(asset, from, sender, amount)?;
+                self.debit(asset, from, amount)?;
+                self.credit(asset, to, amount)?;
+                vec![Event::Transfer {
+                    asset,
+                    from,
+                    to,
+                    amount,
+                }]
+            }
+            TransactionData::Batch(actions) => {
+                // all or nothing: roll back every earlier action if a later one fails
+                let snapshot = self.snapshot();
+                let mut events = vec![];
+                for (index, action) in actions.into_iter().enumerate() {
+                    match self.apply(sender, action, session) {
+                        Ok(action_events) => events.extend(action_events),
+                        Err(error) => {
+                            self.restore(snapshot);
+                            return Err(RollupError::BatchFailed {
+                                index,
+                                error: Box::new(error),
+                            });
+                        }
+                    }
+                }
+                events
+            }
+            TransactionData::RegisterSessionKey { key, expiry, scope } => {
+                // an account can't be its own session key (every transaction it signs would be
+                // held to the key's scope), and a policy account has no key to sign with
+                if key == sender || self.accounts.contains_key(&key) {
+                    return Err(RollupError::Unauthorized);
+                }
+                // a key only ever signs for one account, until it expires
+                if let Some(session_key) = self.session_keys.get(&key) {
+                    if session_key.account != sender && !self.expired(&session_key.expiry) {
+                        return Err(RollupError::Unauthorized);
+                    }
+                }
+                self.session_keys.insert(
+                    key,
+                    SessionKey {
+                        account: sender,
+                        expiry,
+                        scope,
+                        spent: U256::ZERO,
+                    },
+                );
+                vec![Event::SessionKeyRegistered {
+                    account: sender,
+                    key,
+                    expiry,
+                }]
+            }
+            TransactionData::RevokeSessionKey(key) => {
+                match self.session_keys.get(&key) {
+                    Some(session_key) if session_key.account == sender => {
+                        self.session_keys.remove(&key);
+                    }
+                    _ => return Err(RollupError::Unauthorized),
+                }
+                vec![Event::SessionKeyRevoked {
+                    account: sender,
+                    key,
+                }]
+            }
+            TransactionData::CreateAccount { policy, salt } => {
+                policy.validate()?;
+                let account = Self::account_address(&sender, salt, &policy);
+                if self.accounts.contains_key(&account) {
+                    return Err(RollupError::Unauthorized);
+                }
+                self.accounts.insert(
+                    account,
+                    PolicyAccount {
+                        policy: policy.clone(),
+                        spent: U256::ZERO,
+                        day: 0,
+                        recovery: None,
+                    },
+                );
+                vec![Event::AccountCreated {
+                    account,
+                    creator: sender,
+                    policy,
+                }]
+            }
+            TransactionData::SetPolicy(policy) => {
+                policy.validate()?;
+                // only policy accounts have a policy to change
+                let Some(account) = self.accounts.get_mut(&sender) else {
+                    return Err(RollupError::Unauthorized);
+                };
+                account.policy.may_change_to(&policy)?;
+                let handed_over = account.policy.control() != policy.control();
+                account.policy = policy.clone();
+                // a recovery started under the old policy doesn't carry over
+                account.recovery = None;
+                let mut events = vec![Event::PolicyChanged {
+                    account: sender,
+                    policy,
+                }];
+                // nor do the session keys the old owners registered
+                if handed_over {
+                    events.extend(self.revoke_session_keys(&sender));
+                }
+                events
+            }
+            TransactionData::StartRecovery { account, owner } => {
+                let timestamp = self.timestamp;
+                let Some(PolicyAccount {
+                    policy:
+                        AccountPolicy::Guardian {
+                            guardian, delay, ..
+                        },
+                    recovery,
+                    ..
+                }) = self.accounts.get_mut(&account)
+                else {
+                    return Err(RollupError::Unauthorized);
+                };
+                if *guardian != sender {
+                    return Err(RollupError::Unauthorized);
+                }
+                if owner == *guardian {
+                    return Err(RollupError::InvalidPolicy(
+                        "the owner can't be their own guardian".to_string(),
+                    ));
+                }
+                let after = timestamp.saturating_add(*delay);
+                *recovery = Some(Recovery { owner, after });
+                vec![Event::RecoveryStarted {
+                    account,
+                    owner,
+                    after,
+                }]
+            }
+            TransactionData::CancelRecovery => {
+                match self.accounts.get_mut(&sender) {
+                    Some(account) if account.recovery.is_some() => account.recovery = None,
+                    _ => return Err(RollupError::NoRecovery),
+                }
+                vec![Event::RecoveryCancelled { account: sender }]
+            }
+            TransactionData::FinishRecovery(account) => {
+                let timestamp = self.timestamp;
+                let Some(PolicyAccount {
+                    policy:
+                        AccountPolicy::Guardian {
+                            owner, guardian, ..
+                        },
+                    recovery,
+                    ..
+                }) = self.accounts.get_mut(&account)
+                else {
+                    return Err(RollupError::Unauthorized);
+                };
+                if *guardian != sender {
+                    return Err(RollupError::Unauthorized);
+                }
+                let Some(pending) = *recovery else {
+                    return Err(RollupError::NoRecovery);
+                };
+                if timestamp < pending.after {
+                    return Err(RollupError::RecoveryNotReady(pending.after));
+                }
+                *owner = pending.owner;
+                *recovery = None;
+                let mut events = vec![Event::PolicyChanged {
+                    account,
+                    policy: self.accounts[&account].policy.clone(),
+                }];
+                // the session keys of the lost key go with it
+                events.extend(self.revoke_session_keys(&account));
+                events
+            }
+            // TransactionData::Extension includes the business logic for the rollup
+            TransactionData::Extension(ext) => match ext {
+                ChessTransactions::ProposeGame {
+                    white,
+                    black,
+                    asset,
+                    wager,
+                } => {
+                    let accepted = if white == sender {
+                        (true, false)
+                    } else if black == sender {
+                        (false, true)
+                    } else {
+                        return Err(RollupError::NotAPlayer);
+                    };
+                    let game_id = self.state.next_game_id;
+                    self.state.pending_games.insert(
+                        game_id,
+                        PendingGame {
+                            white,
+                            black,
+                            accepted,
+                            asset,
+                            wager,
+                        },
+                    );
+                    self.state.next_game_id += U256::from(1);
+                    vec![Event::Extension(ChessEvents::GameProposed {
+                        game_id,
+                        white,
+                        black,
+                        asset,
+                        wager,
+                    })]
+                }
+                ChessTransactions::StartGame(game_id) => {
+                    let Some(pending_game) = self.state.pending_games.get(&game_id) else {
+                        return Err(RollupError::UnknownGame(game_id));
+                    };
+                    // only the player who didn't propose the game can accept it
+                    let acceptor = match pending_game.accepted {
+                        (true, false) => pending_game.black,
+                        (false, true) => pending_game.white,
+                        _ => return Err(RollupError::GameNotActive(game_id)),
+                    };
+                    if sender != acceptor {
+                        return Err(RollupError::NotAPlayer);
+                    }
+
+                    let (white, black, asset, wager) = (
+                        pending_game.white,
+                        pending_game.black,
+                        pending_game.asset,
+                        pending_game.wager,
+                    );
+                    let Some(pot) = wager.checked_mul(U256::from(2)) else {
+                        return Err(RollupError::BalanceOverflow(white));
+                    };
+                    self.debit(asset, white, wager)?;
+                    if let Err(e) = self.debit(asset, black, wager) {
+                        // undo white's debit so the rejected transaction leaves no trace
+                        self.credit(asset, white, wager)?;
+                        return Err(e);
+                    }
+
+                    self.state.games.insert(
+                        game_id,
+                        Game {
+                            turns: 0,
+                            board: Board::default().to_string(),
+                            white,
+                            black,
+                            asset,
+                            wager: pot,
+                            status: "ongoing".to_string(),
+                        },
+                    );
+                    self.state.pending_games.remove(&game_id);
+                    vec![Event::Extension(ChessEvents::GameStarted(game_id))]
+                }
+                ChessTransactions::Move { game_id, san } => {
+                    let Some(game) = self.state.games.get(&game_id) else {
+                        return Err(RollupError::UnknownGame(game_id));
+                    };
+                    if game.status != "ongoing" {
+                        return Err(RollupError::GameNotActive(game_id));
+                    }
+
+                    if game.turns % 2 == 0 && sender != game.white {
+                        return Err(RollupError::NotYourTurn);
+                    } else if game.turns % 2 == 1 && sender != game.black {
+                        return Err(RollupError::NotYourTurn);
+                    }
+
+                    let board = Board::from_str(&game.board).unwrap();
+                    let Ok(mov) = san.parse::<ChessMove>() else {
+                        return Err(RollupError::InvalidMove(san));
+                    };
+                    if !board.legal(mov) {
+                        return Err(RollupError::InvalidMove(san));
+                    }
+                    let board = board.make_move_new(mov);
+                    let (white, black, asset, wager) =
+                        (game.white, game.black, game.asset, game.wager);
+
+                    let mut events = vec![Event::Extension(ChessEvents::MoveMade {
+                        game_id,
+                        player: sender,
+                        san,
+                    })];
+
+                    // settle the wager before touching the game, so a failed payout leaves no trace
+                    let status = match board.status() {
+                        BoardStatus::Checkmate => {
+                            self.credit(asset, sender, wager)?;
+                            events.push(Event::Extension(ChessEvents::GameEnded {
+                                game_id,
+                                winner: Some(sender),
+                            }));
+                            format!("{} won", sender)
+                        }
+                        BoardStatus::Stalemate => {
+                            // check both payouts before making either
+                            let half = wager / U256::from(2);
+                            let white_balance = self.balance_of(&asset, &white).checked_add(half);
+                            let black_balance = if white == black {
+                                white_balance
+                            } else {
+                                Some(self.balance_of(&asset, &black))
+                            };
+                            if white_balance.is_none() {
+                                return Err(RollupError::BalanceOverflow(white));
+                            }
+                            if black_balance.and_then(|b| b.checked_add(half)).is_none() {
+                                return Err(RollupError::BalanceOverflow(black));
+                            }
+                            self.credit(asset, white, half)?;
+                            self.credit(asset, black, half)?;
+                            events.push(Event::Extension(ChessEvents::GameEnded {
+                                game_id,
+                                winner: None,
+                            }));
+                            "stalemate".to_string()
+                        }
+                        BoardStatus::Ongoing => "ongoing".to_string(),
+                    };
+
+                    let game = self.state.games.get_mut(&game_id).unwrap();
+                    game.board = board.to_string();
+                    game.turns += 1;
+                    game.status = status;
+                    events
+                }
+                ChessTransactions::Resign(game_id) => {
+                    let Some(game) = self.state.games.get(&game_id) else {
+                        return Err(RollupError::UnknownGame(game_id));
+                    };
+                    if game.status != "ongoing" {
+                        return Err(RollupError::GameNotActive(game_id));
+                    }
+                    let winner = if sender == game.white {
+                        game.black
+                    } else if sender == game.black {
+                        game.white
+                    } else {
+                        return Err(RollupError::NotAPlayer);
+                    };
+
+                    self.credit(game.asset, winner, game.wager)?;
+                    self.state.games.get_mut(&game_id).unwrap().status =
+                        format!("{} resigned", sender);
+                    vec![Event::Extension(ChessEvents::GameEnded {
+                        game_id,
+                        winner: Some(winner),
+                    })]
+                }
+            },
+        };
+        if let Some((key, value)) = spends {
+            if let Some(session_key) = self.session_keys.get_mut(&key) {
+                session_key.spent = session_key.spent.saturating_add(value);
+            }
+        }
+        Ok(events)
+    }
+}
+
+/// Session keys can be limited to some of the chess actions, to a single game, or to a wager.
+/// Whatever can lose a player their wager moves it: proposing or starting a game, resigning, and
+/// a move that ends the game.
+impl ScopedExtension<ChessTransactions> for FullRollupState {
+    fn describe(&self, ext: &ChessTransactions) -> Action {
+        // the wager each player put in (games hold the pot of both)
+        let stake = |game_id: &U256| {
+            self.state
+                .games
+                .get(game_id)
+                .map(|game| game.wager / U256::from(2))
+                .unwrap_or_default()
+        };
+        match ext {
+            ChessTransactions::ProposeGame { wager, .. } => Action {
+                name: "ProposeGame",
+                target: None,
+                value: *wager,
+            },
+            ChessTransactions::StartGame(game_id) => Action {
+                name: "StartGame",
+                target: Some(*game_id),
+                value: self
+                    .state
+                    .pending_games
+                    .get(game_id)
+                    .map(|pending_game| pending_game.wager)
+                    .unwrap_or_default(),
+            },
+            ChessTransactions::Move { game_id, san } => Action {
+                name: "Move",
+                target: Some(*game_id),
+                value: if self.ends_game(game_id, san) {
+                    stake(game_id)
+                } else {
+                    U256::ZERO
+                },
+            },
+            ChessTransactions::Resign(game_id) => Action {
+                name: "Resign",
+                target: Some(*game_id),
+                value: stake(game_id),
+            },
+        }
+    }
+}
+
+impl FullRollupState {
+    /// Whether `san` is a legal move in the game that ends it (checkmate or stalemate)
+    fn ends_game(&self, game_id: &U256, san: &str) -> bool {
+        let Some(game) = self.state.games.get(game_id) else {
+            return false;
+        };
+        let (Ok(board), Ok(mov)) = (Board::from_str(&game.board), san.parse::<ChessMove>()) else {
+            return false;
+        };
+        board.legal(mov) && board.make_move_new(mov).status() != BoardStatus::Ongoing
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounts::DAY;
+    use crate::rollup_lib::{SessionScope, ValidUntil, EVENT_GAS, GAS_TOKEN, TX_GAS};
+    use crate::testing::*;
+
+    fn transfer(from: AlloyAddress, to: AlloyAddress, amount: u64) -> Data {
+        TransactionData::Transfer {
+            asset: GAS_TOKEN,
+            from,
+            to,
+            amount: U256::from(amount),
+        }
+    }
+
+    /// Create an account with `policy`, as `creator`, and return its address
+    fn create(state: &mut FullRollupState, creator: u8, policy: AccountPolicy) -> AlloyAddress {
+        let nonce = state.nonce_of(&address(creator)).to::<u64>();
+        let create = TransactionData::CreateAccount {
+            policy: policy.clone(),
+            salt: U256::ZERO,
+        };
+        state
+            .execute(sign(creator, transaction(nonce, create)))
+            .unwrap();
+        FullRollupState::account_address(&address(creator), U256::ZERO, &policy)
+    }
+
+    fn register(key: AlloyAddress, actions: &[&str], max_value: Option<u64>) -> Data {
+        TransactionData::RegisterSessionKey {
+            key,
+            expiry: ValidUntil::Timestamp(u64::MAX),
+            scope: SessionScope {
+                actions: actions.iter().map(|action| action.to_string()).collect(),
+                target: None,
+                max_value: max_value.map(U256::from),
+            },
+        }
+    }
+
+    fn chess(ext: ChessTransactions) -> Data {
+        TransactionData::Extension(ext)
+    }
+
+    fn mov(game_id: u64, san: &str) -> Data {
+        chess(ChessTransactions::Move {
+            game_id: U256::from(game_id),
+            san: san.to_string(),
+        })
+    }
+
+    #[test]
+    fn only_the_owner_transfers_their_funds() {
+        let mut state = FullRollupState::default();
+        let (owner, thief) = (address(1), address(2));
+        deposit(&mut state, owner, 100);
+
+        let steal = sign(2, transaction(0, transfer(owner, thief, 100)));
+        assert_eq!(state.execute(steal), Err(RollupError::Unauthorized));
+        assert_eq!(balance(&state, owner), U256::from(100));
+        assert_eq!(state.nonce_of(&thief), U256::ZERO);
+    }
+
+    #[test]
+    fn transfer_from_spends_the_allowance_unless_it_is_unlimited() {
+        let mut state = FullRollupState::default();
+        let (owner, spender, to) = (address(1), address(2), address(3));
+        deposit(&mut state, owner, 100);
+        let approve = |amount| TransactionData::Approve {
+            asset: GAS_TOKEN,
+            spender,
+            amount,
+        };
+        let transfer_from = |nonce, amount| {
+            let data = TransactionData::TransferFrom {
+                asset: GAS_TOKEN,
+                from: owner,
+                to,
+                amount: U256::from(amount),
+            };
+            sign(2, transaction(nonce, data))
+        };
+
+        state
+            .execute(sign(1, transaction(0, approve(U256::from(30)))))
+            .unwrap();
+        state.execute(transfer_from(0, 20)).unwrap();
+        assert_eq!(
+            state.allowance(&GAS_TOKEN, &owner, &spender),
+            U256::from(10)
+        );
+        assert_eq!(
+            state.execute(transfer_from(1, 20)),
+            Err(RollupError::InsufficientAllowance {
+                asset: GAS_TOKEN,
+                owner,
+                spender,
+                needed: U256::from(20),
+                available: U256::from(10),
+            })
+        );
+
+        state
+            .execute(sign(1, transaction(1, approve(U256::MAX))))
+            .unwrap();
+        state.execute(transfer_from(1, 40)).unwrap();
+        state.execute(transfer_from(2, 40)).unwrap();
+        assert_eq!(state.allowance(&GAS_TOKEN, &owner, &spender), U256::MAX);
+        assert_eq!(balance(&state, to), U256::from(100));
+        assert_eq!(balance(&state, owner), U256::ZERO);
+    }
+
+    #[test]
+    fn a_failing_batch_action_undoes_the_ones_before_it() {
+        let mut state = FullRollupState::default();
+        let (from, to) = (address(1), address(3));
+        deposit(&mut state, from, 100);
+
+        let batch = TransactionData::Batch(vec![transfer(from, to, 10), transfer(from, to, 1_000)]);
+        assert!(matches!(
+            state.execute(sign(1, transaction(0, batch))),
+            Err(RollupError::BatchFailed { index: 1, .. })
+        ));
+        assert_eq!(balance(&state, from), U256::from(100));
+        assert_eq!(balance(&state, to), U256::ZERO);
+        assert_eq!(state.nonce_of(&from), U256::ZERO);
+    }
+
+    #[test]
+    fn a_failing_batch_leaves_balances_nonces_allowances_and_games_as_they_were() {
+        let mut state = FullRollupState::default();
+        let (white, black) = (address(1), address(3));
+        deposit(&mut state, white, 100);
+        deposit(&mut state, black, 100);
+        let propose = chess(ChessTransactions::ProposeGame {
+            white,
+            black,
+            asset: GAS_TOKEN,
+            wager: U256::from(20),
+        });
+        state
+            .execute(sign(1, transaction(0, propose.clone())))
+            .unwrap();
+
+        let batch = TransactionData::Batch(vec![
+            TransactionData::Approve {
+                asset: GAS_TOKEN,
+                spender: address(2),
+                amount: U256::from(30),
+            },
+            transfer(black, address(4), 10),
+            chess(ChessTransactions::StartGame(U256::ZERO)),
+            propose,
+            transfer(black, address(4), 1_000),
+        ]);
+        assert!(matches!(
+            state.execute(sign(3, transaction(0, batch))),
+            Err(RollupError::BatchFailed { index: 4, .. })
+        ));
+        assert_eq!(balance(&state, white), U256::from(100));
+        assert_eq!(balance(&state, black), U256::from(100));
+        assert_eq!(balance(&state, address(4)), U256::ZERO);
+        assert_eq!(state.nonce_of(&black), U256::ZERO);
+        assert_eq!(state.allowance(&GAS_TOKEN, &black, &address(2)), U256::ZERO);
+        assert!(state.state.games.is_empty());
+        assert!(state.state.pending_games.contains_key(&U256::ZERO));
+        assert_eq!(state.state.next_game_id, U256::from(1));
+
+        // and the game can still be started on its own
+        let start = chess(ChessTransactions::StartGame(U256::ZERO));
+        state.execute(sign(3, transaction(0, start))).unwrap();
+        assert_eq!(balance(&state, black), U256::from(80));
+    }
+
+    #[test]
+    fn sponsors_pay_the_fee_up_to_their_max_fee() {
+        let mut state = FullRollupState::default();
+        let (user, to, payer, recipient) = (address(1), address(3), address(4), address(9));
+        state.fee_per_gas = U256::from(1);
+        state.fee_recipient = recipient;
+        deposit(&mut state, user, 10);
+        deposit(&mut state, payer, 100_000);
+        // one Transfer event
+        let fee = TX_GAS + EVENT_GAS;
+
+        let stx = sign(1, transaction(0, transfer(user, to, 10)));
+        state.execute(sponsor(4, fee, stx)).unwrap();
+        assert_eq!(balance(&state, user), U256::ZERO);
+        assert_eq!(balance(&state, payer), U256::from(100_000 - fee));
+        assert_eq!(balance(&state, recipient), U256::from(fee));
+
+        let stx = sign(3, transaction(0, transfer(to, user, 10)));
+        assert_eq!(
+            state.execute(sponsor(4, fee - 1, stx)),
+            Err(RollupError::FeeTooHigh {
+                fee: U256::from(fee),
+                max_fee: U256::from(fee - 1),
+            })
+        );
+        assert_eq!(balance(&state, to), U256::from(10));
+        assert_eq!(balance(&state, payer), U256::from(100_000 - fee));
+        assert_eq!(state.nonce_of(&to), U256::ZERO);
+    }
+
+    /// A state that charges 1 per gas to address(9), where address(1) has 100 and the sponsor
+    /// address(4) has `sponsor_balance`
+    fn with_fees(sponsor_balance: u64) -> FullRollupState {
+        let mut state = FullRollupState {
+            fee_per_gas: U256::from(1),
+            fee_recipient: address(9),
+            ..Default::default()
+        };
+        deposit(&mut state, address(1), 100);
+        deposit(&mut state, address(4), sponsor_balance);
+        state
+    }
+
+    #[test]
+    fn a_sponsor_who_cant_pay_undoes_the_transaction() {
+        let fee = TX_GAS + EVENT_GAS;
+        let mut state = with_fees(fee - 1);
+        let stx = sign(1, transaction(0, transfer(address(1), address(3), 10)));
+        assert_eq!(
+            state.execute(sponsor(4, fee, stx)),
+            Err(RollupError::InsufficientFunds {
+                asset: GAS_TOKEN,
+                account: address(4),
+                needed: U256::from(fee),
+                available: U256::from(fee - 1),
+            })
+        );
+        assert_eq!(balance(&state, address(1)), U256::from(100));
+        assert_eq!(balance(&state, address(3)), U256::ZERO);
+        assert_eq!(state.nonce_of(&address(1)), U256::ZERO);
+    }
+
+    #[test]
+    fn sponsorships_cant_be_replayed() {
+        let fee = TX_GAS + EVENT_GAS;
+        let mut state = with_fees(10 * fee);
+        let sponsored = sponsor(
+            4,
+            fee,
+            sign(1, transaction(0, transfer(address(1), address(3), 10))),
+        );
+        state.execute(sponsored.clone()).unwrap();
+
+        // not as it was, nor for another transaction of the same sender
+        assert!(matches!(
+            state.execute(sponsored.clone()),
+            Err(RollupError::BadNonce { .. })
+        ));
+        let another = SignedTransaction {
+            sponsorship: sponsored.sponsorship,
+            ..sign(1, transaction(1, transfer(address(1), address(3), 10)))
+        };
+        assert_eq!(state.execute(another), Err(RollupError::BadSignature));
+        assert_eq!(balance(&state, address(4)), U256::from(9 * fee));
+        assert_eq!(balance(&state, address(3)), U256::from(10));
+    }
+
+    #[test]
+    fn a_fee_above_max_fee_undoes_the_transaction() {
+        let fee = TX_GAS + EVENT_GAS;
+        let mut state = with_fees(10 * fee);
+        // enough for one transfer, but a batch of two emits another event
+        let batch = TransactionData::Batch(vec![
+            transfer(address(1), address(3), 10),
+            transfer(address(1), address(3), 10),
+        ]);
+        assert_eq!(
+            state.execute(sponsor(4, fee, sign(1, transaction(0, batch)))),
+            Err(RollupError::FeeTooHigh {
+                fee: U256::from(fee + EVENT_GAS),
+                max_fee: U256::from(fee),
+            })
+        );
+        assert_eq!(balance(&state, address(1)), U256::from(100));
+        assert_eq!(balance(&state, address(3)), U256::ZERO);
+        assert_eq!(balance(&state, address(4)), U256::from(10 * fee));
+        assert_eq!(state.nonce_of(&address(1)), U256::ZERO);
+    }
+
+    #[test]
+    fn multisig_accounts_need_threshold_owners_to_sign() {
+        let mut state = FullRollupState::default();
+        let policy = AccountPolicy::Multisig {
+            owners: vec![address(1), address(2), address(3)],
+            threshold: 2,
+        };
+        let account = create(&mut state, 1, policy);
+        deposit(&mut state, account, 100);
+        let send = |signers: &[u8]| {
+            sign_for(
+                account,
+                signers,
+                transaction(0, transfer(account, address(4), 10)),
+            )
+        };
+
+        assert_eq!(
+            state.execute(send(&[1])),
+            Err(RollupError::NotEnoughSignatures { needed: 2, got: 1 })
+        );
+        assert_eq!(state.execute(send(&[4, 1])), Err(RollupError::BadSignature));
+        state.execute(send(&[1, 3])).unwrap();
+        assert_eq!(balance(&state, address(4)), U256::from(10));
+    }
+
+    #[test]
+    fn spending_limits_start_over_every_day() {
+        let mut state = FullRollupState::default();
+        let policy = AccountPolicy::SpendingLimit {
+            owner: address(1),
+            asset: GAS_TOKEN,
+            daily_limit: U256::from(50),
+        };
+        let account = create(&mut state, 1, policy);
+        deposit(&mut state, account, 200);
+        let send = |nonce, amount| {
+            sign_for(
+                account,
+                &[1],
+                transaction(nonce, transfer(account, address(3), amount)),
+            )
+        };
+
+        state.execute(send(0, 30)).unwrap();
+        assert_eq!(
+            state.execute(send(1, 30)),
+            Err(RollupError::SpendingLimitExceeded {
+                asset: GAS_TOKEN,
+                allowed: U256::from(20),
+                spent: U256::from(30),
+            })
+        );
+        assert_eq!(balance(&state, account), U256::from(170));
+        state.timestamp += DAY;
+        state.execute(send(1, 30)).unwrap();
+        assert_eq!(balance(&state, account), U256::from(140));
+    }
+
+    #[test]
+    fn guardians_hand_the_account_over_after_the_delay_unless_cancelled() {
+        let mut state = FullRollupState::default();
+        let (owner, guardian, new_owner) = (1, 5, 6);
+        let policy = AccountPolicy::Guardian {
+            owner: address(owner),
+            guardian: address(guardian),
+            delay: 100,
+        };
+        let account = create(&mut state, owner, policy);
+        deposit(&mut state, account, 100);
+        let start = |nonce| {
+            let start = TransactionData::StartRecovery {
+                account,
+                owner: address(new_owner),
+            };
+            sign(guardian, transaction(nonce, start))
+        };
+        let finish = |nonce| {
+            sign(
+                guardian,
+                transaction(nonce, TransactionData::FinishRecovery(account)),
+            )
+        };
+
+        state.execute(start(0)).unwrap();
+        assert_eq!(
+            state.execute(finish(1)),
+            Err(RollupError::RecoveryNotReady(100))
+        );
+        // the owner still has their key, and stops it
+        state
+            .execute(sign_for(
+                account,
+                &[owner],
+                transaction(0, TransactionData::CancelRecovery),
+            ))
+            .unwrap();
+        state.timestamp += 100;
+        assert_eq!(state.execute(finish(1)), Err(RollupError::NoRecovery));
+
+        state.execute(start(1)).unwrap();
+        state.timestamp += 99;
+        assert_eq!(
+            state.execute(finish(2)),
+            Err(RollupError::RecoveryNotReady(200))
+        );
+        state.timestamp += 1;
+        state.execute(finish(2)).unwrap();
+        let send = |signer| {
+            sign_for(
+                account,
+                &[signer],
+                transaction(1, transfer(account, address(3), 10)),
+            )
+        };
+        assert_eq!(state.execute(send(owner)), Err(RollupError::BadSignature));
+        state.execute(send(new_owner)).unwrap();
+    }
+
+    #[test]
+    fn session_keys_spend_up_to_max_value_in_total() {
+        let mut state = FullRollupState::default();
+        let (owner, other) = (address(1), address(3));
+        deposit(&mut state, owner, 100);
+        let key = address(2);
+        state
+            .execute(sign(
+                1,
+                transaction(0, register(key, &["Transfer"], Some(10))),
+            ))
+            .unwrap();
+
+        let send =
+            |nonce, amount| sign_as(2, owner, transaction(nonce, transfer(owner, other, amount)));
+        state.execute(send(1, 6)).unwrap();
+        assert_eq!(state.session_keys[&key].spent, U256::from(6));
+        assert_eq!(state.execute(send(2, 6)), Err(RollupError::OutOfScope));
+        state.execute(send(2, 4)).unwrap();
+        assert_eq!(state.session_keys[&key].spent, U256::from(10));
+        assert_eq!(state.execute(send(3, 1)), Err(RollupError::OutOfScope));
+        assert_eq!(balance(&state, other), U256::from(10));
+    }
+
+    #[test]
+    fn session_keys_count_every_action_of_a_batch_once_it_succeeds() {
+        let mut state = FullRollupState::default();
+        let (owner, other) = (address(1), address(3));
+        deposit(&mut state, owner, 100);
+        let key = address(2);
+        state
+            .execute(sign(
+                1,
+                transaction(0, register(key, &["Transfer"], Some(10))),
+            ))
+            .unwrap();
+
+        let batch =
+            TransactionData::Batch(vec![transfer(owner, other, 6), transfer(owner, other, 6)]);
+        assert_eq!(
+            state.execute(sign_as(2, owner, transaction(1, batch))),
+            Err(RollupError::BatchFailed {
+                index: 1,
+                error: Box::new(RollupError::OutOfScope),
+            })
+        );
+        // what fails isn't spent
+        let too_much = sign_as(2, owner, transaction(1, transfer(owner, other, 1_000)));
+        assert_eq!(state.execute(too_much), Err(RollupError::OutOfScope));
+        state
+            .execute(sign(
+                1,
+                transaction(1, register(key, &["Transfer"], Some(1_000))),
+            ))
+            .unwrap();
+        let too_much = sign_as(2, owner, transaction(2, transfer(owner, other, 1_000)));
+        assert!(matches!(
+            state.execute(too_much),
+            Err(RollupError::InsufficientFunds { .. })
+        ));
+        assert_eq!(state.session_keys[&key].spent, U256::ZERO);
+
+        let batch =
+            TransactionData::Batch(vec![transfer(owner, other, 6), transfer(owner, other, 4)]);
+        state
+            .execute(sign_as(2, owner, transaction(2, batch)))
+            .unwrap();
+        assert_eq!(state.session_keys[&key].spent, U256::from(10));
+    }
+
+    #[test]
+    fn session_keys_risk_the_wager_when_resigning_or_ending_the_game() {
+        let mut state = FullRollupState::default();
+        let (white, black) = (address(1), address(3));
+        deposit(&mut state, white, 100);
+        deposit(&mut state, black, 100);
+        let propose = chess(ChessTransactions::ProposeGame {
+            white,
+            black,
+            asset: GAS_TOKEN,
+            wager: U256::from(40),
+        });
+        state.execute(sign(1, transaction(0, propose))).unwrap();
+        let start = chess(ChessTransactions::StartGame(U256::ZERO));
+        state.execute(sign(3, transaction(0, start))).unwrap();
+        state
+            .execute(sign(
+                1,
+                transaction(1, register(address(2), &["Move", "Resign"], Some(39))),
+            ))
+            .unwrap();
+        state
+            .execute(sign(
+                3,
+                transaction(1, register(address(4), &["Move"], Some(40))),
+            ))
+            .unwrap();
+
+        // resigning forfeits the 40 white put in
+        let resign = chess(ChessTransactions::Resign(U256::ZERO));
+        assert_eq!(
+            state.execute(sign_as(2, white, transaction(2, resign))),
+            Err(RollupError::OutOfScope)
+        );
+
+        // fool's mate: only the last move ends the game
+        state
+            .execute(sign_as(2, white, transaction(2, mov(0, "f2f3"))))
+            .unwrap();
+        state
+            .execute(sign_as(4, black, transaction(2, mov(0, "e7e5"))))
+            .unwrap();
+        state
+            .execute(sign_as(2, white, transaction(3, mov(0, "g2g4"))))
+            .unwrap();
+        assert_eq!(state.session_keys[&address(2)].spent, U256::ZERO);
+        assert_eq!(state.session_keys[&address(4)].spent, U256::ZERO);
+        state
+            .execute(sign_as(4, black, transaction(3, mov(0, "d8h4"))))
+            .unwrap();
+        assert_eq!(state.session_keys[&address(4)].spent, U256::from(40));
+        assert_eq!(balance(&state, black), U256::from(140));
+    }
+
+    #[test]
+    fn block_deadlines_pass_as_blocks_are_sealed() {
+        let mut state = FullRollupState::default();
+        let (from, to) = (address(1), address(3));
+        deposit(&mut state, from, 100);
+        let until_block = |nonce, block| {
+            let mut tx = transaction(nonce, transfer(from, to, 1));
+            tx.valid_until = Some(ValidUntil::Block(block));
+            sign(1, tx)
+        };
+
+        // block 0 is still open
+        state.execute(until_block(0, 0)).unwrap();
+        state.seal_block(1);
+        assert_eq!(
+            state.execute(until_block(1, 0)),
+            Err(RollupError::Expired(ValidUntil::Block(0)))
+        );
+        state.execute(until_block(1, 1)).unwrap();
+        assert_eq!(balance(&state, to), U256::from(2));
+    }
+
+    #[test]
+    fn session_keys_go_when_control_of_the_account_changes() {
+        let mut state = FullRollupState::default();
+        let (owner, guardian, new_owner) = (1, 5, 6);
+        let policy = AccountPolicy::Guardian {
+            owner: address(owner),
+            guardian: address(guardian),
+            delay: 100,
+        };
+        let create = TransactionData::CreateAccount {
+            policy: policy.clone(),
+            salt: U256::ZERO,
+        };
+        state.execute(sign(owner, transaction(0, create))).unwrap();
+        let account = FullRollupState::account_address(&address(owner), U256::ZERO, &policy);
+        deposit(&mut state, account, 100);
+        let session = |nonce| {
+            sign_as(
+                2,
+                account,
+                transaction(nonce, transfer(account, address(3), 1)),
+            )
+        };
+
+        state
+            .execute(sign_for(
+                account,
+                &[owner],
+                transaction(0, register(address(2), &["Transfer"], None)),
+            ))
+            .unwrap();
+        state.execute(session(1)).unwrap();
+
+        // the guardian hands the account to a new owner, and the lost key's session keys go
+        let start = TransactionData::StartRecovery {
+            account,
+            owner: address(new_owner),
+        };
+        state
+            .execute(sign(guardian, transaction(0, start)))
+            .unwrap();
+        state.timestamp += 100;
+        state
+            .execute(sign(
+                guardian,
+                transaction(1, TransactionData::FinishRecovery(account)),
+            ))
+            .unwrap();
+        assert!(state.session_keys.is_empty());
+        assert_eq!(state.execute(session(2)), Err(RollupError::BadSignature));
+
+        // a new policy with the same owner keeps them, one with another owner doesn't
+        state
+            .execute(sign_for(
+                account,
+                &[new_owner],
+                transaction(2, register(address(2), &["Transfer"], None)),
+            ))
+            .unwrap();
+        let longer = TransactionData::SetPolicy(AccountPolicy::Guardian {
+            owner: address(new_owner),
+            guardian: address(guardian),
+            delay: 200,
+        });
+        state
+            .execute(sign_for(account, &[new_owner], transaction(3, longer)))
+            .unwrap();
+        assert_eq!(state.session_keys.len(), 1);
+        let handed_over = TransactionData::SetPolicy(AccountPolicy::Guardian {
+            owner: address(7),
+            guardian: address(guardian),
+            delay: 200,
+        });
+        state
+            .execute(sign_for(account, &[new_owner], transaction(4, handed_over)))
+            .unwrap();
+        assert!(state.session_keys.is_empty());
+    }
+}
@@ -0,0 +1,56 @@
+use crate::rollup_lib::{Asset, BaseRollupState};
+use alloy_primitives::{keccak256, Address as AlloyAddress, FixedBytes, U256};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Everything a new rollup starts with, as read from genesis.json (at the root of this repo).
+/// The sequencer starts from it on first boot, and the elf_program is built for its hash, so
+/// that proofs are only ever made for the rollup that started from it.
+/// NOTE: pre-funded balances aren't backed by deposits to the bridge, so they can only be
+/// withdrawn on L1 if the bridge holds those funds some other way.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Genesis<S> {
+    /// the L1 chain the bridge is deployed on
+    pub l1_chain_id: u64,
+    /// the bridge contract on L1, which deposits come from and withdrawals are claimed on
+    pub bridge: AlloyAddress,
+    /// deposits are read from the L1 block after this one (e.g. the one the bridge was deployed in)
+    pub l1_block: U256,
+    /// pre-funded accounts: asset => account => amount
+    #[serde(default)]
+    pub balances: BTreeMap<Asset, BTreeMap<AlloyAddress, U256>>,
+    /// the fee schedule to start with (zero: no fees)
+    #[serde(default)]
+    pub fee_per_gas: U256,
+    #[serde(default)]
+    pub fee_recipient: AlloyAddress,
+    /// the extension state S to start with
+    pub state: S,
+}
+
+impl<S: Serialize> Genesis<S> {
+    /// The keccak of the genesis as JSON (re-encoded, so whitespace and key order in the file
+    /// don't matter). Every state root of the rollup commits to it.
+    pub fn hash(&self) -> FixedBytes<32> {
+        keccak256(serde_json::to_vec(self).unwrap())
+    }
+}
+
+impl<S: Serialize + Clone, T, E> BaseRollupState<S, T, E>
+where
+    Self: Default,
+{
+    /// The state a rollup starts from, before its first block
+    pub fn from_genesis(genesis: &Genesis<S>) -> Self {
+        let mut state = Self::default();
+        state.genesis_hash = genesis.hash();
+        state.l1_chain_id = genesis.l1_chain_id;
+        state.bridge = genesis.bridge;
+        state.l1_block = genesis.l1_block;
+        state.balances = genesis.balances.clone();
+        state.fee_per_gas = genesis.fee_per_gas;
+        state.fee_recipient = genesis.fee_recipient;
+        state.state = genesis.state.clone();
+        state
+    }
+}
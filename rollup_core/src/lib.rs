@@ -0,0 +1,26 @@
+//! Everything the sequencer, the zkVM program and the prover extension have to agree on.
+//! The engine is compiled once from this crate for every target, so the code the sequencer
+//! executes is the code the proof is generated for.
+pub mod accounts;
+pub mod engine;
+pub mod genesis;
+pub mod rollup_lib;
+
+#[cfg(feature = "persistence")]
+pub mod persistence;
+#[cfg(feature = "prover")]
+pub mod prover_types;
+#[cfg(feature = "sp1-zkvm")]
+pub mod zkvm;
+#[cfg(test)]
+mod testing;
+
+// fingerprint of this crate's source, written by build.rs
+include!(concat!(env!("OUT_DIR"), "/source_hash.rs"));
+
+/// What a program built from this crate says it proves: this engine, from the genesis with
+/// `genesis_hash`. The elf_program holds it as is (and commits to it, see zkvm::prove_blocks),
+/// so the sequencer can find it in the ELF it embeds.
+pub fn built_for(genesis_hash: alloy_primitives::FixedBytes<32>) -> String {
+    format!("rollup-core {SOURCE_HASH} genesis {genesis_hash}")
+}
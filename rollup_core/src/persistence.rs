@@ -0,0 +1,332 @@
+use crate::rollup_lib::{
+    Asset, BaseRollupState, WithdrawAccumulator, WithdrawalRecord, WithdrawalStatus, GAS_TOKEN,
+};
+use alloy_primitives::{Address as AlloyAddress, U256};
+#[cfg(feature = "kinode")]
+use kinode_process_lib::{get_state, set_state};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::cell::Cell;
+use std::collections::BTreeMap;
+
+/// Persistence is how the sequencer keeps the rollup state across restarts
+/// It is kept out of the ExecutionEngine so that the EE does not depend on kinode
+/// (and encode/decode work outside of kinode too, e.g. to read saved state in the replay tool)
+pub trait Persistence: Sized {
+    #[cfg(feature = "kinode")]
+    fn save(&self) -> anyhow::Result<()>;
+    /// None if nothing has been saved yet (i.e. on first boot, see Genesis). Fails (rather than
+    /// starting over from genesis) if the saved state can't be read
+    #[cfg(feature = "kinode")]
+    fn load() -> anyhow::Result<Option<Self>>;
+    /// The state as saved, with the current codec (e.g. to keep a snapshot of it elsewhere)
+    fn encode(&self) -> anyhow::Result<Vec<u8>>;
+    /// Read state encoded with either codec, and from any known version
+    fn decode(bytes: &[u8]) -> anyhow::Result<Self>;
+}
+
+/// The version of the saved state. Bump it (and add a migration) whenever the state changes in
+/// a way serde can't read old state into, e.g. a new field that can't just be `#[serde(default)]`
+pub const STATE_VERSION: u32 = 1;
+
+/// A migration upgrades the saved state (as JSON) by one version
+type Migration = fn(&mut serde_json::Value) -> anyhow::Result<()>;
+
+/// MIGRATIONS[n] upgrades state from version n to version n + 1.
+/// Version 0 is the state saved before it was versioned, without an envelope around it.
+const MIGRATIONS: [Migration; STATE_VERSION as usize] = [from_baseline];
+
+/// The state is saved with the version of its format, so that it can be migrated when it changes
+#[derive(Serialize)]
+struct Envelope<'a, T> {
+    version: u32,
+    state: &'a T,
+}
+
+/// How the state is encoded when it is saved. JSON can be read and edited by hand; bincode is
+/// much more compact and faster to save, and (since every map in the state is ordered) always
+/// encodes the same state to the same bytes. Loading works with either, whatever the codec.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Codec {
+    Json,
+    Bincode,
+}
+
+/// Bincode state starts with this, followed by the version (u32, little endian) and the state.
+/// JSON state always starts with '{', so the two can't be mistaken for each other.
+const BINCODE_MAGIC: &[u8; 4] = b"RBS\0";
+
+thread_local! {
+    /// The codec `save` uses: the one the saved state was loaded with, unless set otherwise
+    static CODEC: Cell<Codec> = Cell::new(Codec::Bincode);
+    /// The L1 chain id and bridge that state from before they were kept in it settles on
+    static L1_SETTINGS: Cell<Option<(u64, AlloyAddress)>> = const { Cell::new(None) };
+}
+
+/// The codec the state is saved with
+pub fn codec() -> Codec {
+    CODEC.with(|codec| codec.get())
+}
+
+/// Save the state with `codec` from now on, e.g. switch to JSON to edit the state by hand,
+/// then back to bincode. Takes effect on the next save.
+pub fn set_codec(codec: Codec) {
+    CODEC.with(|current| current.set(codec));
+}
+
+/// The L1 chain and bridge to migrate unversioned state to (which doesn't say), i.e. those in
+/// the genesis.json of the rollup that saved it. Until this is set, such state can't be loaded.
+pub fn set_l1_settings(chain_id: u64, bridge: AlloyAddress) {
+    L1_SETTINGS.with(|settings| settings.set(Some((chain_id, bridge))));
+}
+
+impl<S, T, E> Persistence for BaseRollupState<S, T, E>
+where
+    Self: Serialize + DeserializeOwned,
+{
+    // logic for saving our state to kinode sequencer
+    // I would not modify this function, but you can if you require special logic
+    // NOTE: serde_json makes manual modification of the state much easier, see set_codec
+    #[cfg(feature = "kinode")]
+    fn save(&self) -> anyhow::Result<()> {
+        set_state(&self.encode()?);
+        Ok(())
+    }
+
+    // logic for loading our state from kinode sequencer
+    // I would not modify this function, but you can if you require special logic
+    #[cfg(feature = "kinode")]
+    fn load() -> anyhow::Result<Option<Self>> {
+        let Some(bytes) = get_state() else {
+            return Ok(None);
+        };
+        // keep saving with the codec the state was saved with
+        set_codec(if bytes.starts_with(BINCODE_MAGIC) {
+            Codec::Bincode
+        } else {
+            Codec::Json
+        });
+        Self::decode(&bytes).map(Some)
+    }
+
+    fn encode(&self) -> anyhow::Result<Vec<u8>> {
+        Ok(match codec() {
+            Codec::Json => serde_json::to_vec(&Envelope {
+                version: STATE_VERSION,
+                state: self,
+            })?,
+            Codec::Bincode => {
+                let mut bytes = BINCODE_MAGIC.to_vec();
+                bytes.extend(STATE_VERSION.to_le_bytes());
+                bincode::serialize_into(&mut bytes, self)?;
+                bytes
+            }
+        })
+    }
+
+    fn decode(bytes: &[u8]) -> anyhow::Result<Self> {
+        if let Some(rest) = bytes.strip_prefix(BINCODE_MAGIC) {
+            let (version, state) = rest.split_at(4.min(rest.len()));
+            let version = u32::from_le_bytes(version.try_into()?);
+            // bincode is only ever read at the current version: migrations work on JSON, and old
+            // bincode state can't be read into the new types
+            if version != STATE_VERSION {
+                return Err(anyhow::anyhow!(
+                    "saved state has version {version}, but this sequencer is at version \
+                     {STATE_VERSION}: switch the sequencer that saved it to the Json codec first"
+                ));
+            }
+            return Ok(bincode::deserialize(state)?);
+        }
+
+        let saved: serde_json::Value = serde_json::from_slice(bytes)?;
+        let (version, mut state) = match saved {
+            serde_json::Value::Object(mut envelope) if envelope.contains_key("version") => {
+                let Some(version) = envelope["version"].as_u64() else {
+                    return Err(anyhow::anyhow!("saved state has a bad version"));
+                };
+                let state = envelope
+                    .remove("state")
+                    .ok_or(anyhow::anyhow!("saved state is missing"))?;
+                (version, state)
+            }
+            unversioned => (0, unversioned),
+        };
+        if version > STATE_VERSION as u64 {
+            return Err(anyhow::anyhow!(
+                "saved state has version {version}, but this sequencer only knows up to version \
+                 {STATE_VERSION}: refusing to start rather than lose it"
+            ));
+        }
+        for (from, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+            migration(&mut state)
+                .map_err(|e| anyhow::anyhow!("migrating state from version {from}: {e}"))?;
+        }
+        Ok(serde_json::from_value(state)?)
+    }
+}
+
+/// 0 -> 1: from the state the sequencer saved before versioning, which only had the gas token:
+/// - balances (a flat map, account => amount), pending withdrawals (account, amount) and batches
+///   move under GAS_TOKEN. Batches keep the claims they were saved with.
+/// - pending withdrawals are kept in incremental trees, and every withdrawal has a record of its
+///   status: both are built for the withdrawals that were pending
+/// - the transactions in `sequenced` were never cleared back then (and got no receipts, which
+///   sealing them into a block needs), so they are dropped: their effects are in the balances
+/// - the L1 chain and bridge are kept in state: the state doesn't say which, so they are the ones
+///   set with set_l1_settings (and it keeps a zero genesis hash, see adopt_genesis in the
+///   sequencer)
+fn from_baseline(state: &mut serde_json::Value) -> anyhow::Result<()> {
+    if !state.is_object() {
+        return Err(anyhow::anyhow!("saved state is not an object"));
+    }
+    let Some((chain_id, bridge)) = L1_SETTINGS.with(|settings| settings.get()) else {
+        return Err(anyhow::anyhow!(
+            "unversioned state doesn't say which L1 chain and bridge it settles on: set them \
+             from genesis.json with set_l1_settings first"
+        ));
+    };
+    let balances: BTreeMap<AlloyAddress, U256> = serde_json::from_value(state["balances"].take())?;
+    let withdrawals: Vec<(AlloyAddress, U256)> =
+        serde_json::from_value(state["withdrawals"].take())?;
+    for batch in state["batches"].as_array_mut().into_iter().flatten() {
+        if let Some(batch) = batch.as_object_mut() {
+            batch.insert("asset".to_string(), serde_json::to_value(GAS_TOKEN)?);
+        }
+    }
+
+    let mut tree = WithdrawAccumulator::default();
+    let mut records: BTreeMap<AlloyAddress, Vec<WithdrawalRecord>> = BTreeMap::new();
+    for (account, amount) in withdrawals.iter() {
+        let index = tree
+            .push(GAS_TOKEN, *account, *amount)
+            .map_err(|e| anyhow::anyhow!("{e}"))?;
+        records.entry(*account).or_default().push(WithdrawalRecord {
+            asset: GAS_TOKEN,
+            amount: *amount,
+            block: 0,
+            batch: None,
+            index,
+            status: WithdrawalStatus::Pending,
+        });
+    }
+    let trees = match withdrawals.is_empty() {
+        true => BTreeMap::new(),
+        false => BTreeMap::from([(GAS_TOKEN, tree)]),
+    };
+    let withdrawals: Vec<(Asset, AlloyAddress, U256)> = withdrawals
+        .into_iter()
+        .map(|(account, amount)| (GAS_TOKEN, account, amount))
+        .collect();
+
+    state["balances"] = serde_json::to_value(BTreeMap::from([(GAS_TOKEN, balances)]))?;
+    state["withdrawals"] = serde_json::to_value(withdrawals)?;
+    state["withdraw_trees"] = serde_json::to_value(trees)?;
+    state["withdrawal_records"] = serde_json::to_value(records)?;
+    state["sequenced"] = serde_json::json!([]);
+    state["l1_chain_id"] = serde_json::json!(chain_id);
+    state["bridge"] = serde_json::to_value(bridge)?;
+    state["accounts"] = serde_json::json!({});
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::FullRollupState;
+    use alloy_primitives::address;
+
+    /// State as the sequencer saved it before versioning (and before assets)
+    const BASELINE_STATE: &str = include_str!("../tests/fixtures/baseline-state.json");
+    const BRIDGE: AlloyAddress = address!("24E063a827CB134315aC57A380446c8bF5418555");
+
+    /// The baseline state, migrated for a rollup that settles on chain 10 at BRIDGE
+    fn baseline() -> FullRollupState {
+        set_l1_settings(10, BRIDGE);
+        FullRollupState::decode(BASELINE_STATE.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn migrates_baseline_state() {
+        let state = baseline();
+        let alice = address!("1111111111111111111111111111111111111111");
+        let bob = address!("2222222222222222222222222222222222222222");
+
+        assert_eq!(state.balance_of(&GAS_TOKEN, &alice), U256::from(100));
+        assert_eq!(state.balance_of(&GAS_TOKEN, &bob), U256::from(16));
+        assert_eq!(state.nonces[&alice], U256::from(1));
+        assert_eq!(state.l1_block, U256::from(7));
+        assert_eq!((state.l1_chain_id, state.bridge), (10, BRIDGE));
+        // the withdrawal in it already took effect
+        assert!(state.sequenced.is_empty());
+
+        assert_eq!(state.withdrawals, vec![(GAS_TOKEN, alice, U256::from(5))]);
+        let record = &state.withdrawal_records[&alice][0];
+        assert_eq!(record.status, WithdrawalStatus::Pending);
+        assert_eq!((record.batch, record.index), (None, 0));
+        assert_eq!(state.withdraw_trees[&GAS_TOKEN].num_drops, 1);
+
+        let batch = &state.batches[0];
+        assert_eq!(batch.asset, GAS_TOKEN);
+        assert!(batch.verified);
+        let claims = batch.claims.as_ref().unwrap();
+        assert_eq!(claims[&bob].amount, U256::from(3));
+    }
+
+    #[test]
+    fn wont_guess_where_baseline_state_settles() {
+        // on a thread of its own, where no L1 settings were set
+        let decoded =
+            std::thread::spawn(|| FullRollupState::decode(BASELINE_STATE.as_bytes()).is_ok());
+        assert!(!decoded.join().unwrap());
+    }
+
+    #[test]
+    fn round_trips_with_either_codec() {
+        let state = baseline();
+        for codec in [Codec::Json, Codec::Bincode] {
+            set_codec(codec);
+            let bytes = state.encode().unwrap();
+            let decoded = FullRollupState::decode(&bytes).unwrap();
+            assert_eq!(decoded.encode().unwrap(), bytes, "{codec:?}");
+            assert_eq!(decoded.state_root(), state.state_root(), "{codec:?}");
+        }
+    }
+
+    #[test]
+    fn tells_the_codecs_apart() {
+        let state = baseline();
+        set_codec(Codec::Bincode);
+        let bincode = state.encode().unwrap();
+        assert!(bincode.starts_with(BINCODE_MAGIC));
+        assert_eq!(bincode[4..8], STATE_VERSION.to_le_bytes());
+        set_codec(Codec::Json);
+        let json = state.encode().unwrap();
+        assert_eq!(json[0], b'{');
+        let saved: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(saved["version"], STATE_VERSION);
+    }
+
+    #[test]
+    fn refuses_old_bincode_state() {
+        let state = baseline();
+        let mut saved = BINCODE_MAGIC.to_vec();
+        saved.extend((STATE_VERSION - 1).to_le_bytes());
+        saved.extend(bincode::serialize(&state).unwrap());
+        let Err(error) = FullRollupState::decode(&saved) else {
+            panic!("decoded bincode state from an old version");
+        };
+        assert!(error.to_string().contains("to the Json codec first"));
+        // nor does a truncated header panic
+        assert!(FullRollupState::decode(&BINCODE_MAGIC[..]).is_err());
+        assert!(FullRollupState::decode(b"RBS\0\x04").is_err());
+    }
+
+    #[test]
+    fn refuses_newer_state() {
+        let saved = serde_json::json!({ "version": STATE_VERSION + 1, "state": {} });
+        let Err(error) = FullRollupState::decode(saved.to_string().as_bytes()) else {
+            panic!("decoded state from a newer version");
+        };
+        assert!(error.to_string().contains("refusing to start"));
+    }
+}
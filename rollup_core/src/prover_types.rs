@@ -0,0 +1,19 @@
+use crate::engine::{ChessTransactions, FullRollupState};
+use crate::rollup_lib::Block;
+use serde::{Deserialize, Serialize};
+use sp1_core::SP1Stdin;
+
+#[derive(Serialize, Deserialize)]
+pub struct ProveRequest {
+    pub elf: Vec<u8>,
+    pub input: SP1Stdin,
+}
+
+/// The zkVM input for replaying `blocks` on top of `state`
+/// NOTE: must be written in the order `zkvm::prove_blocks` reads it
+pub fn prover_input(state: &FullRollupState, blocks: &Vec<Block<ChessTransactions>>) -> SP1Stdin {
+    let mut stdin = SP1Stdin::new();
+    stdin.write(state);
+    stdin.write(blocks);
+    stdin
+}
@@ -0,0 +1,1876 @@
+use crate::accounts::{account_key, AccountPolicy, PolicyAccount};
+use alloy_primitives::{keccak256, Address as AlloyAddress, Bytes, FixedBytes, Signature, U256};
+use alloy_sol_types::{sol, SolValue};
+use serde::{ser::SerializeStruct, Deserialize, Serialize, Serializer};
+use std::collections::{BTreeMap, VecDeque};
+
+sol! {
+    struct Node {
+        uint256 index;
+        address account;
+        address asset;
+        uint256 amount;
+    }
+
+    struct Header {
+        uint64 number;
+        bytes32 parentHash;
+        uint64 timestamp;
+        uint256 l1Block;
+        bytes32 transactionsRoot;
+        bytes32 stateRoot;
+        bytes32 receiptsRoot;
+    }
+}
+
+/// Every balance on the rollup is denominated in an Asset: the address of the bridged token on L1.
+/// The gas token (ETH) uses the zero address.
+pub type Asset = AlloyAddress;
+pub const GAS_TOKEN: Asset = AlloyAddress::ZERO;
+
+/// Rollup state must contain:
+/// - a list of sealed blocks (used for proving the computation on-chain), and the header of the
+///   latest block that was archived out of it once proven
+/// - a list of sequenced transactions (not yet sealed into a block)
+/// - the receipts of all sequenced transactions, by transaction hash (the receipt's block number
+///   and index in turn locate the transaction itself)
+/// - the timestamp of the block currently being built
+/// - list of balances (asset => account => amount)
+/// - a map of nonces (for replay protection)
+/// - a map of allowances (asset => owner => spender => amount, for moving funds on someone's behalf)
+/// - a map of session keys (key => the account it signs for, until when, and what it may do)
+/// - a map of policy accounts (account => the policy its transactions are validated by)
+/// - a list of pending withdrawals (not yet included in a batch), and the trees they make up
+/// - a list of batches (withdrawal tree roots that users can withdraw against on L1, one per asset)
+/// - the fee schedule: what a unit of gas costs, and who collects the fees
+/// - the genesis it started from, and the L1 chain and bridge it settles on
+/// - additional state S, which can be anything. In this repo, we use it for storing chess game state
+/// T are the extension transactions that act on S, and E the extension events they emit
+#[derive(Serialize, Deserialize)]
+pub struct BaseRollupState<S, T, E> {
+    #[serde(default = "Vec::new")]
+    pub blocks: Vec<Block<T>>,
+    /// the header of the latest archived block (see archive_blocks): `blocks` continues from it
+    #[serde(default)]
+    pub archived: Option<BlockHeader>,
+    pub sequenced: Vec<SignedTransaction<T>>,
+    #[serde(default = "BTreeMap::new")]
+    pub receipts: BTreeMap<FixedBytes<32>, Receipt<E>>,
+    #[serde(default)]
+    pub timestamp: u64,
+    /// state trees of the latest sealed blocks, kept in memory to serve state proofs
+    #[serde(skip)]
+    pub state_trees: VecDeque<(u64, SparseMerkleTree)>,
+    pub balances: BTreeMap<Asset, BTreeMap<AlloyAddress, U256>>,
+    pub nonces: BTreeMap<AlloyAddress, U256>,
+    #[serde(default)]
+    pub allowances: BTreeMap<Asset, BTreeMap<AlloyAddress, BTreeMap<AlloyAddress, U256>>>,
+    #[serde(default)]
+    pub session_keys: BTreeMap<AlloyAddress, SessionKey>,
+    #[serde(default)]
+    pub accounts: BTreeMap<AlloyAddress, PolicyAccount>,
+    pub withdrawals: Vec<(Asset, AlloyAddress, U256)>,
+    /// the withdrawal trees of the next batch, one per asset
+    #[serde(default)]
+    pub withdraw_trees: BTreeMap<Asset, WithdrawAccumulator>,
+    /// every withdrawal of each account, tracking where its funds are until they are claimed on L1
+    #[serde(default)]
+    pub withdrawal_records: BTreeMap<AlloyAddress, Vec<WithdrawalRecord>>,
+    pub batches: Vec<WithdrawTree>,
+    pub l1_block: U256,
+    /// fees are paid in the gas token, per unit of gas used, to `fee_recipient` (zero: no fees)
+    #[serde(default)]
+    pub fee_per_gas: U256,
+    #[serde(default)]
+    pub fee_recipient: AlloyAddress,
+    /// the hash of the genesis the rollup started from (see Genesis::hash), or zero for rollups
+    /// that started before genesis files
+    #[serde(default)]
+    pub genesis_hash: FixedBytes<32>,
+    pub l1_chain_id: u64,
+    pub bridge: AlloyAddress,
+    pub state: S,
+}
+
+/// Sequenced transactions are periodically sealed into blocks. Blocks give provers, indexers
+/// and the UI a stable unit to refer to, and chain together through their parent hashes.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Block<T> {
+    pub header: BlockHeader,
+    pub transactions: Vec<SignedTransaction<T>>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct BlockHeader {
+    pub number: u64,
+    pub parent_hash: FixedBytes<32>,
+    pub timestamp: u64,
+    /// the latest L1 block whose deposits are included (the L1 origin of this block)
+    pub l1_block: U256,
+    pub transactions_root: FixedBytes<32>,
+    pub state_root: FixedBytes<32>,
+    pub receipts_root: FixedBytes<32>,
+}
+
+impl BlockHeader {
+    /// The block hash is the keccak of the ABI-encoded header, so that it can be checked on L1
+    pub fn hash(&self) -> FixedBytes<32> {
+        keccak256(
+            &Header {
+                number: self.number,
+                parentHash: self.parent_hash,
+                timestamp: self.timestamp,
+                l1Block: self.l1_block,
+                transactionsRoot: self.transactions_root,
+                stateRoot: self.state_root,
+                receiptsRoot: self.receipts_root,
+            }
+            .abi_encode(),
+        )
+    }
+}
+
+impl<S: StateCommitment, T: Serialize, E: Serialize> BaseRollupState<S, T, E> {
+    /// Seal all sequenced transactions into a new block. `next_timestamp` becomes the timestamp
+    /// of the block that is opened in its place.
+    pub fn seal_block(&mut self, next_timestamp: u64) -> &Block<T> {
+        let transactions = std::mem::take(&mut self.sequenced);
+        let number = self.block_number();
+        let state_tree = self.state_tree();
+        let header = BlockHeader {
+            number,
+            parent_hash: self
+                .latest_header()
+                .map(|header| header.hash())
+                .unwrap_or_default(),
+            timestamp: self.timestamp,
+            l1_block: self.l1_block,
+            transactions_root: merkle_root(transactions.iter().map(|tx| tx.hash()).collect()),
+            state_root: state_tree.root(),
+            receipts_root: merkle_root(
+                transactions
+                    .iter()
+                    .map(|tx| self.receipts[&tx.hash()].hash())
+                    .collect(),
+            ),
+        };
+        self.blocks.push(Block {
+            header,
+            transactions,
+        });
+        self.state_trees.push_back((number, state_tree));
+        if self.state_trees.len() > STATE_TREE_HISTORY {
+            self.state_trees.pop_front();
+        }
+        self.timestamp = next_timestamp;
+        self.blocks.last().unwrap()
+    }
+
+    /// The state tree as of a sealed block, if it is recent enough to still be around
+    pub fn state_tree_at(&self, number: u64) -> Option<SparseMerkleTree> {
+        if let Some((_, tree)) = self.state_trees.iter().find(|(n, _)| *n == number) {
+            return Some(tree.clone());
+        }
+        // nothing has happened since the latest block was sealed, so its state is the current one
+        if self.sequenced.is_empty() && number + 1 == self.block_number() {
+            return Some(self.state_tree());
+        }
+        None
+    }
+}
+
+impl<S: StateCommitment, T: Serialize + Clone, E: Serialize> BaseRollupState<S, T, E>
+where
+    Self: ExecutionEngine<T>,
+{
+    /// Re-execute a sealed block on top of this state (e.g. inside the zkVM), checking that it
+    /// seals to exactly the same header.
+    pub fn replay_block(&mut self, block: &Block<T>) -> Result<(), RollupError> {
+        if !self.sequenced.is_empty() || block.header.number != self.block_number() {
+            return Err(RollupError::InvalidBlock(block.header.number));
+        }
+        self.timestamp = block.header.timestamp;
+        for tx in block.transactions.iter() {
+            self.execute(tx.clone())?;
+        }
+        if self.seal_block(block.header.timestamp).header != block.header {
+            return Err(RollupError::InvalidBlock(block.header.number));
+        }
+        Ok(())
+    }
+}
+
+/// The extension state S has to tell the rollup how to commit to it: as a list of
+/// (key, value) leaves of the state tree. Keys should be made with `state_key` and a namespace
+/// that is unique to the extension, and zero values are treated as absent.
+pub trait StateCommitment {
+    fn leaves(&self) -> Vec<(FixedBytes<32>, FixedBytes<32>)>;
+}
+
+/// Leaf keys are the keccak of a namespace followed by the parts that identify the leaf
+pub fn state_key(namespace: &str, parts: &[&[u8]]) -> FixedBytes<32> {
+    keccak256([&[namespace.as_bytes()], parts].concat().concat())
+}
+
+pub fn balance_key(asset: &Asset, account: &AlloyAddress) -> FixedBytes<32> {
+    state_key("balance", &[asset.as_slice(), account.as_slice()])
+}
+
+pub fn nonce_key(account: &AlloyAddress) -> FixedBytes<32> {
+    state_key("nonce", &[account.as_slice()])
+}
+
+pub fn allowance_key(
+    asset: &Asset,
+    owner: &AlloyAddress,
+    spender: &AlloyAddress,
+) -> FixedBytes<32> {
+    state_key(
+        "allowance",
+        &[asset.as_slice(), owner.as_slice(), spender.as_slice()],
+    )
+}
+
+pub fn session_key_key(key: &AlloyAddress) -> FixedBytes<32> {
+    state_key("session_key", &[key.as_slice()])
+}
+
+impl<S: StateCommitment, T, E> BaseRollupState<S, T, E> {
+    /// Every piece of the rollup state (apart from its history) as a leaf of the state tree
+    pub fn state_tree(&self) -> SparseMerkleTree {
+        let mut leaves = Vec::new();
+        for (asset, accounts) in self.balances.iter() {
+            for (account, balance) in accounts.iter() {
+                leaves.push((balance_key(asset, account), FixedBytes::from(*balance)));
+            }
+        }
+        for (account, nonce) in self.nonces.iter() {
+            leaves.push((nonce_key(account), FixedBytes::from(*nonce)));
+        }
+        for (asset, owners) in self.allowances.iter() {
+            for (owner, spenders) in owners.iter() {
+                for (spender, amount) in spenders.iter() {
+                    leaves.push((
+                        allowance_key(asset, owner, spender),
+                        FixedBytes::from(*amount),
+                    ));
+                }
+            }
+        }
+        for (key, session_key) in self.session_keys.iter() {
+            leaves.push((
+                session_key_key(key),
+                keccak256(serde_json::to_vec(session_key).unwrap()),
+            ));
+        }
+        for (address, account) in self.accounts.iter() {
+            leaves.push((
+                account_key(address),
+                keccak256(serde_json::to_vec(account).unwrap()),
+            ));
+        }
+        for (asset, tree) in self.withdraw_trees.iter() {
+            leaves.push((state_key("withdraw_root", &[asset.as_slice()]), tree.root()));
+        }
+        for (index, batch) in self.batches.iter().enumerate() {
+            leaves.push((
+                state_key("batch", &[&U256::from(index).to_be_bytes::<32>()]),
+                batch.root,
+            ));
+        }
+        leaves.push((state_key("l1_block", &[]), FixedBytes::from(self.l1_block)));
+        leaves.push((
+            state_key("fee_per_gas", &[]),
+            FixedBytes::from(self.fee_per_gas),
+        ));
+        leaves.push((
+            state_key("fee_recipient", &[]),
+            self.fee_recipient.into_word(),
+        ));
+        leaves.push((state_key("genesis", &[]), self.genesis_hash));
+        leaves.extend(self.state.leaves());
+        SparseMerkleTree::new(leaves)
+    }
+
+    pub fn state_root(&self) -> FixedBytes<32> {
+        self.state_tree().root()
+    }
+}
+
+/// How many sealed blocks back state proofs can be served for
+pub const STATE_TREE_HISTORY: usize = 16;
+
+/// The rollup state is committed to with a (compact) sparse Merkle tree: each leaf sits on the
+/// path given by the bits of its key, as close to the root as it can be without sharing a
+/// subtree with another leaf. Empty subtrees are zero, so the tree stays small and the same
+/// set of leaves always produces the same root, no matter the order they were inserted in.
+#[derive(Clone, Debug, Default)]
+pub struct SparseMerkleTree {
+    leaves: BTreeMap<FixedBytes<32>, FixedBytes<32>>,
+}
+
+impl SparseMerkleTree {
+    pub fn new(leaves: Vec<(FixedBytes<32>, FixedBytes<32>)>) -> Self {
+        Self {
+            leaves: leaves
+                .into_iter()
+                .filter(|(_, value)| *value != FixedBytes::ZERO)
+                .collect(),
+        }
+    }
+
+    pub fn get(&self, key: &FixedBytes<32>) -> Option<&FixedBytes<32>> {
+        self.leaves.get(key)
+    }
+
+    /// Every (non-zero) leaf, by key
+    pub fn leaves(&self) -> &BTreeMap<FixedBytes<32>, FixedBytes<32>> {
+        &self.leaves
+    }
+
+    pub fn root(&self) -> FixedBytes<32> {
+        let leaves: Vec<(FixedBytes<32>, FixedBytes<32>)> =
+            self.leaves.iter().map(|(k, v)| (*k, *v)).collect();
+        Self::subtree_root(&leaves, 0)
+    }
+
+    /// Prove the value of `key`: its leaf if it is in the tree, or whatever its path ends at if not
+    pub fn prove(&self, key: &FixedBytes<32>) -> StateProof {
+        let leaves: Vec<(FixedBytes<32>, FixedBytes<32>)> =
+            self.leaves.iter().map(|(k, v)| (*k, *v)).collect();
+        let mut path = &leaves[..];
+        let mut siblings = Vec::new();
+        let mut depth = 0;
+        while path.len() > 1 {
+            let split = path.partition_point(|(k, _)| !Self::bit(k, depth));
+            let (left, right) = path.split_at(split);
+            if Self::bit(key, depth) {
+                siblings.push(Self::subtree_root(left, depth + 1));
+                path = right;
+            } else {
+                siblings.push(Self::subtree_root(right, depth + 1));
+                path = left;
+            }
+            depth += 1;
+        }
+        StateProof {
+            siblings,
+            leaf: match path {
+                [(k, v)] if k != key => Some((*k, *v)),
+                _ => None,
+            },
+        }
+    }
+
+    /// `leaves` are sorted by key, and all share the first `depth` bits
+    fn subtree_root(leaves: &[(FixedBytes<32>, FixedBytes<32>)], depth: usize) -> FixedBytes<32> {
+        match leaves {
+            [] => FixedBytes::ZERO,
+            [(key, value)] => Self::leaf_hash(key, value),
+            _ => {
+                let split = leaves.partition_point(|(key, _)| !Self::bit(key, depth));
+                Self::node_hash(
+                    Self::subtree_root(&leaves[..split], depth + 1),
+                    Self::subtree_root(&leaves[split..], depth + 1),
+                )
+            }
+        }
+    }
+
+    fn leaf_hash(key: &FixedBytes<32>, value: &FixedBytes<32>) -> FixedBytes<32> {
+        keccak256([&[0u8][..], key.as_slice(), value.as_slice()].concat())
+    }
+
+    fn node_hash(left: FixedBytes<32>, right: FixedBytes<32>) -> FixedBytes<32> {
+        keccak256([&[1u8][..], left.as_slice(), right.as_slice()].concat())
+    }
+
+    /// the bit of `key` that decides which way to go at `depth` (0 is the most significant bit)
+    fn bit(key: &FixedBytes<32>, depth: usize) -> bool {
+        key[depth / 8] >> (7 - depth % 8) & 1 == 1
+    }
+}
+
+/// Proof that a key holds some value in a state tree, or that it is absent (holds zero).
+/// Siblings are the roots of the subtrees next to the key's path, from the root down.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct StateProof {
+    pub siblings: Vec<FixedBytes<32>>,
+    /// when the key is absent, the other leaf (key, value) its path ends at, if any
+    pub leaf: Option<(FixedBytes<32>, FixedBytes<32>)>,
+}
+
+/// Check that `key` holds `value` (zero meaning absent) in the state tree with root `root`.
+/// This is all a light client (or the elf_program) needs to trust a value served over RPC.
+pub fn verify_state_proof(
+    root: FixedBytes<32>,
+    key: FixedBytes<32>,
+    value: FixedBytes<32>,
+    proof: &StateProof,
+) -> bool {
+    let mut node = match (value == FixedBytes::ZERO, proof.leaf) {
+        (false, None) => SparseMerkleTree::leaf_hash(&key, &value),
+        (true, None) => FixedBytes::ZERO,
+        (true, Some((other_key, other_value))) => {
+            if other_key == key || other_value == FixedBytes::ZERO {
+                return false;
+            }
+            SparseMerkleTree::leaf_hash(&other_key, &other_value)
+        }
+        (false, Some(_)) => return false,
+    };
+    for (depth, sibling) in proof.siblings.iter().enumerate().rev() {
+        node = if SparseMerkleTree::bit(&key, depth) {
+            SparseMerkleTree::node_hash(*sibling, node)
+        } else {
+            SparseMerkleTree::node_hash(node, *sibling)
+        };
+    }
+    node == root
+}
+
+/// Root of an ordered binary Merkle tree over `leaves`. A node without a sibling is carried up
+/// to the next layer unchanged, and the root of an empty tree is zero.
+pub fn merkle_root(mut layer: Vec<FixedBytes<32>>) -> FixedBytes<32> {
+    if layer.is_empty() {
+        return FixedBytes::ZERO;
+    }
+    while layer.len() > 1 {
+        layer = layer
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => keccak256(&[*left, *right].concat()),
+                [single] => *single,
+                _ => unreachable!(),
+            })
+            .collect();
+    }
+    layer[0]
+}
+
+/// a SignedTransaction is just a wrapper around the different operations that your rollup supports
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SignedTransaction<T> {
+    pub pub_key: AlloyAddress, // TODO: get rid of this - superfluous!
+    pub sig: Signature,
+    pub tx: Transaction<T>,
+    /// set when someone else pays the fees for this transaction
+    #[serde(default)]
+    pub sponsorship: Option<Sponsorship>,
+    /// the signatures of the other signers of a policy account (see AccountPolicy::Multisig)
+    #[serde(default)]
+    pub cosigs: Vec<Signature>,
+}
+
+/// A sponsor (e.g. a relayer, or the team funding onboarding) pays the fees of a transaction
+/// on behalf of its sender, up to `max_fee`. The sponsor signs the sender's signature, which
+/// commits to the transaction, so a sponsorship can't be moved to another transaction.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Sponsorship {
+    pub sponsor: AlloyAddress,
+    pub max_fee: U256,
+    pub sig: Signature,
+}
+
+/// What a sponsor signs (with personal_sign, over its JSON)
+#[derive(Serialize)]
+struct SponsorMessage {
+    sig: Bytes,
+    max_fee: U256,
+}
+
+impl<T: Serialize> SignedTransaction<T> {
+    /// The canonical identifier of a transaction: the keccak of its bincode encoding, which
+    /// (unlike JSON) is always the same for the same transaction
+    pub fn hash(&self) -> FixedBytes<32> {
+        keccak256(
+            bincode::serialize(&(
+                &self.pub_key,
+                &self.sig,
+                &self.tx,
+                &self.sponsorship,
+                &self.cosigs,
+            ))
+            .unwrap(),
+        )
+    }
+
+    /// Checks that the sponsor, if there is one, signed for this transaction
+    pub fn verify_sponsorship(&self) -> Result<(), RollupError> {
+        let Some(sponsorship) = &self.sponsorship else {
+            return Ok(());
+        };
+        let message = SponsorMessage {
+            sig: Bytes::from(self.sig.as_bytes().to_vec()),
+            max_fee: sponsorship.max_fee,
+        };
+        match sponsorship
+            .sig
+            .recover_address_from_msg(serde_json::to_string(&message).unwrap().as_bytes())
+        {
+            Ok(signer) if signer == sponsorship.sponsor => Ok(()),
+            _ => Err(RollupError::BadSignature),
+        }
+    }
+
+    /// The address that signed the transaction (with personal_sign, over its JSON)
+    pub fn signer(&self) -> Result<AlloyAddress, RollupError> {
+        self.sig
+            // TODO json doesn't (de)serialize deterministically. Alternatively, use ETH RLP?
+            .recover_address_from_msg(&serde_json::to_string(&self.tx).unwrap().as_bytes())
+            .map_err(|_| RollupError::BadSignature)
+    }
+}
+
+/// Transaction wraps the actual data that you want to execute.
+/// Right now it just contains the data, a nonce and an optional expiry, but later it will also
+/// need to include gas gasPrice, gasLimit, etc. (TODO)
+#[derive(Deserialize, Clone, Debug)]
+pub struct Transaction<T> {
+    pub data: TransactionData<T>,
+    pub nonce: U256,
+    /// The sender's deadline for sequencing this transaction, e.g. so a move signed for an old
+    /// position can't be played long after the fact. Transactions without one never expire.
+    #[serde(default)]
+    pub valid_until: Option<ValidUntil>,
+}
+
+// NOTE: Serialize is implemented by hand so that an unset `valid_until` is left out of the JSON
+// (which is what gets signed), while binary formats like bincode, which can't skip fields,
+// always get every field.
+impl<T: Serialize> Serialize for Transaction<T> {
+    fn serialize<Ser: Serializer>(&self, serializer: Ser) -> Result<Ser::Ok, Ser::Error> {
+        let skip_valid_until = self.valid_until.is_none() && serializer.is_human_readable();
+        let mut tx = serializer.serialize_struct("Transaction", 3 - skip_valid_until as usize)?;
+        tx.serialize_field("data", &self.data)?;
+        tx.serialize_field("nonce", &self.nonce)?;
+        if skip_valid_until {
+            tx.skip_field("valid_until")?;
+        } else {
+            tx.serialize_field("valid_until", &self.valid_until)?;
+        }
+        tx.end()
+    }
+}
+
+/// The last point in time at which a transaction may still be sequenced, as seen by the block
+/// it would be sequenced into
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValidUntil {
+    /// the last rollup block number
+    Block(u64),
+    /// the last rollup block timestamp (in seconds)
+    Timestamp(u64),
+}
+
+/// All rollups must support a few basic transactions:
+/// - depositing tokens from L1 to L2
+/// - withdrawing tokens from L2 to L1, in batches the sequencer seals
+/// - transferring any bridged asset between accounts
+/// - approving a spender, who can then transfer an asset on the owner's behalf
+/// - batching several of the above under one signature and nonce, all or nothing
+/// - registering session keys, which can sign a limited set of transactions for the account
+/// - creating policy accounts, changing their policy, and recovering them through a guardian
+/// Any remaining "special" transactions can be handled by the extension field.
+/// For instance, in this repo we use it for starting chess games, moving pieces, etc.
+///
+/// New variants go at the end: the hash of a transaction is over its bincode encoding, which
+/// numbers the variants in the order they are declared.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum TransactionData<T> {
+    BridgeTokens {
+        asset: Asset,
+        amount: U256,
+        block: U256,
+    },
+    WithdrawTokens {
+        asset: Asset,
+        amount: U256,
+    },
+    Transfer {
+        asset: Asset,
+        from: AlloyAddress,
+        to: AlloyAddress,
+        amount: U256,
+    },
+    Approve {
+        asset: Asset,
+        spender: AlloyAddress,
+        amount: U256,
+    },
+    TransferFrom {
+        asset: Asset,
+        from: AlloyAddress,
+        to: AlloyAddress,
+        amount: U256,
+    },
+    Extension(T),
+    Batch(Vec<TransactionData<T>>),
+    RegisterSessionKey {
+        key: AlloyAddress,
+        expiry: ValidUntil,
+        scope: SessionScope,
+    },
+    RevokeSessionKey(AlloyAddress),
+    /// create a policy account at BaseRollupState::account_address
+    CreateAccount {
+        policy: AccountPolicy,
+        salt: U256,
+    },
+    /// replace the policy of the sending policy account, see AccountPolicy::may_change_to
+    SetPolicy(AccountPolicy),
+    /// the guardian of `account` starts handing it to `owner`
+    StartRecovery {
+        account: AlloyAddress,
+        owner: AlloyAddress,
+    },
+    /// the sending policy account cancels the recovery its guardian started
+    CancelRecovery,
+    /// the guardian of `account` completes a recovery once its delay has passed
+    FinishRecovery(AlloyAddress),
+    /// the sequencer seals the pending withdrawals into batches (see seal_withdrawals). Like a
+    /// deposit, this is an unsigned system transaction that users can't send themselves.
+    SealWithdrawals,
+}
+
+/// Every way a transaction can be rejected by the execution engine.
+/// A rejected transaction leaves the rollup state untouched (including the sender's nonce).
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum RollupError {
+    InsufficientFunds {
+        asset: Asset,
+        account: AlloyAddress,
+        needed: U256,
+        available: U256,
+    },
+    BalanceOverflow(AlloyAddress),
+    BadNonce {
+        expected: U256,
+        got: U256,
+    },
+    BadSignature,
+    Unauthorized,
+    InsufficientAllowance {
+        asset: Asset,
+        owner: AlloyAddress,
+        spender: AlloyAddress,
+        needed: U256,
+        available: U256,
+    },
+    UnknownGame(U256),
+    GameNotActive(U256),
+    NotAPlayer,
+    NotYourTurn,
+    InvalidMove(String),
+    InvalidBlock(u64),
+    Expired(ValidUntil),
+    /// a session key signed something outside of its scope
+    OutOfScope,
+    /// the fee is more than the sponsor signed for
+    FeeTooHigh {
+        fee: U256,
+        max_fee: U256,
+    },
+    /// the action at `index` of a batch failed, so the whole batch was rolled back
+    BatchFailed {
+        index: usize,
+        error: Box<RollupError>,
+    },
+    /// fewer owners of a multisig account signed than its threshold
+    NotEnoughSignatures {
+        needed: usize,
+        got: usize,
+    },
+    /// a policy account would spend more of `asset` today than its limit allows
+    SpendingLimitExceeded {
+        asset: Asset,
+        allowed: U256,
+        spent: U256,
+    },
+    InvalidPolicy(String),
+    /// the recovery can only be finished from this timestamp on
+    RecoveryNotReady(u64),
+    NoRecovery,
+    NoWithdrawals,
+    /// the pending withdrawals of `asset` would add up to more than a U256
+    WithdrawalOverflow(Asset),
+}
+
+impl std::fmt::Display for RollupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RollupError::InsufficientFunds {
+                asset,
+                account,
+                needed,
+                available,
+            } => write!(
+                f,
+                "insufficient funds: {account} needs {needed} of {asset} but has {available}"
+            ),
+            RollupError::BalanceOverflow(account) => write!(f, "balance overflow for {account}"),
+            RollupError::BadNonce { expected, got } => {
+                write!(f, "bad nonce: expected {expected}, got {got}")
+            }
+            RollupError::BadSignature => write!(f, "bad signature"),
+            RollupError::Unauthorized => write!(f, "signer is not allowed to do this"),
+            RollupError::InsufficientAllowance {
+                asset,
+                owner,
+                spender,
+                needed,
+                available,
+            } => write!(
+                f,
+                "insufficient allowance: {spender} needs {needed} of {asset} from {owner} but may spend {available}"
+            ),
+            RollupError::UnknownGame(game_id) => write!(f, "game {game_id} doesn't exist"),
+            RollupError::GameNotActive(game_id) => write!(f, "game {game_id} is not active"),
+            RollupError::NotAPlayer => write!(f, "not a player in this game"),
+            RollupError::NotYourTurn => write!(f, "not your turn"),
+            RollupError::InvalidMove(san) => write!(f, "invalid move: {san}"),
+            RollupError::InvalidBlock(number) => {
+                write!(f, "block {number} doesn't match its re-execution")
+            }
+            RollupError::Expired(ValidUntil::Block(block)) => {
+                write!(f, "transaction expired after block {block}")
+            }
+            RollupError::Expired(ValidUntil::Timestamp(timestamp)) => {
+                write!(f, "transaction expired after timestamp {timestamp}")
+            }
+            RollupError::OutOfScope => write!(f, "session key is not allowed to do this"),
+            RollupError::FeeTooHigh { fee, max_fee } => {
+                write!(f, "fee {fee} is more than the sponsor's max fee {max_fee}")
+            }
+            RollupError::BatchFailed { index, error } => {
+                write!(f, "action {index} of the batch failed: {error}")
+            }
+            RollupError::NotEnoughSignatures { needed, got } => {
+                write!(f, "{got} of the {needed} signatures the account needs")
+            }
+            RollupError::SpendingLimitExceeded {
+                asset,
+                allowed,
+                spent,
+            } => write!(
+                f,
+                "spending limit exceeded: spent {spent} of {asset} but only {allowed} is left today"
+            ),
+            RollupError::InvalidPolicy(reason) => write!(f, "invalid policy: {reason}"),
+            RollupError::RecoveryNotReady(after) => {
+                write!(f, "recovery can't be finished before timestamp {after}")
+            }
+            RollupError::NoRecovery => write!(f, "no recovery in progress"),
+            RollupError::NoWithdrawals => write!(f, "no pending withdrawals to seal"),
+            RollupError::WithdrawalOverflow(asset) => {
+                write!(f, "pending withdrawals of {asset} overflow")
+            }
+        }
+    }
+}
+
+impl std::error::Error for RollupError {}
+
+/// Balance helpers: every balance change in the engine should go through these so that
+/// underflows and overflows are rejected instead of panicking or wrapping.
+impl<S, T, E> BaseRollupState<S, T, E> {
+    pub fn balance_of(&self, asset: &Asset, account: &AlloyAddress) -> U256 {
+        self.balances
+            .get(asset)
+            .and_then(|accounts| accounts.get(account))
+            .copied()
+            .unwrap_or(U256::ZERO)
+    }
+
+    /// Revoke every session key of `account`, e.g. when control of it changes
+    pub fn revoke_session_keys(&mut self, account: &AlloyAddress) -> Vec<Event<E>> {
+        let keys: Vec<AlloyAddress> = self
+            .session_keys
+            .iter()
+            .filter(|(_, session_key)| session_key.account == *account)
+            .map(|(key, _)| *key)
+            .collect();
+        keys.into_iter()
+            .map(|key| {
+                self.session_keys.remove(&key);
+                Event::SessionKeyRevoked {
+                    account: *account,
+                    key,
+                }
+            })
+            .collect()
+    }
+
+    pub fn nonce_of(&self, account: &AlloyAddress) -> U256 {
+        *self.nonces.get(account).unwrap_or(&U256::ZERO)
+    }
+
+    /// Whether `until` has passed for the open block
+    pub fn expired(&self, until: &ValidUntil) -> bool {
+        match until {
+            ValidUntil::Block(block) => self.block_number() > *block,
+            ValidUntil::Timestamp(timestamp) => self.timestamp > *timestamp,
+        }
+    }
+
+    /// Fails if the transaction's `valid_until` has passed for the open block
+    pub fn check_valid_until(&self, tx: &Transaction<T>) -> Result<(), RollupError> {
+        match tx.valid_until {
+            Some(until) if self.expired(&until) => Err(RollupError::Expired(until)),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn credit(
+        &mut self,
+        asset: Asset,
+        account: AlloyAddress,
+        amount: U256,
+    ) -> Result<(), RollupError> {
+        let Some(balance) = self.balance_of(&asset, &account).checked_add(amount) else {
+            return Err(RollupError::BalanceOverflow(account));
+        };
+        self.balances
+            .entry(asset)
+            .or_default()
+            .insert(account, balance);
+        Ok(())
+    }
+
+    pub fn debit(
+        &mut self,
+        asset: Asset,
+        account: AlloyAddress,
+        amount: U256,
+    ) -> Result<(), RollupError> {
+        let available = self.balance_of(&asset, &account);
+        let Some(balance) = available.checked_sub(amount) else {
+            return Err(RollupError::InsufficientFunds {
+                asset,
+                account,
+                needed: amount,
+                available,
+            });
+        };
+        self.balances
+            .entry(asset)
+            .or_default()
+            .insert(account, balance);
+        Ok(())
+    }
+
+    /// The number the block currently being built will have once it is sealed
+    pub fn block_number(&self) -> u64 {
+        self.latest_header()
+            .map(|header| header.number + 1)
+            .unwrap_or(0)
+    }
+
+    /// The header of the latest sealed block, whether it is still in `blocks` or archived
+    pub fn latest_header(&self) -> Option<&BlockHeader> {
+        self.blocks
+            .last()
+            .map(|block| &block.header)
+            .or(self.archived.as_ref())
+    }
+
+    /// Queue a withdrawal for the next batch of its asset
+    pub fn withdraw(
+        &mut self,
+        asset: Asset,
+        account: AlloyAddress,
+        amount: U256,
+    ) -> Result<(), RollupError> {
+        let index = self
+            .withdraw_trees
+            .entry(asset)
+            .or_default()
+            .push(asset, account, amount)?;
+        self.withdrawals.push((asset, account, amount));
+        let block = self.block_number();
+        self.withdrawal_records
+            .entry(account)
+            .or_default()
+            .push(WithdrawalRecord {
+                asset,
+                amount,
+                block,
+                batch: None,
+                index,
+                status: WithdrawalStatus::Pending,
+            });
+        Ok(())
+    }
+
+    /// The batches seal_withdrawals would make: the index of each, with the withdrawals
+    /// (account, amount) in it. The state only keeps the roots, so whoever posts the batches has
+    /// to keep those around to generate proofs with (see WithdrawTree::claim).
+    pub fn next_batches(&self) -> Vec<(usize, Vec<(AlloyAddress, U256)>)> {
+        self.withdraw_trees
+            .keys()
+            .enumerate()
+            .map(|(i, asset)| {
+                let withdrawals = self
+                    .withdrawals
+                    .iter()
+                    .filter(|(a, _, _)| a == asset)
+                    .map(|(_, account, amount)| (*account, *amount))
+                    .collect();
+                (self.batches.len() + i, withdrawals)
+            })
+            .collect()
+    }
+
+    /// Turn the pending withdrawals into one batch per asset, ordered by asset address.
+    /// Returns the indexes of the new batches.
+    pub fn seal_withdrawals(&mut self) -> std::ops::Range<usize> {
+        let first = self.batches.len();
+        for (asset, tree) in std::mem::take(&mut self.withdraw_trees) {
+            let batch = self.batches.len();
+            for record in self.withdrawal_records.values_mut().flatten() {
+                if record.asset == asset && record.status == WithdrawalStatus::Pending {
+                    record.batch = Some(batch);
+                    record.status = WithdrawalStatus::Batched;
+                }
+            }
+            self.batches.push(WithdrawTree {
+                asset,
+                root: tree.root(),
+                token_total: tree.token_total,
+                num_drops: tree.num_drops,
+                verified: false,
+                claims: None,
+            });
+        }
+        self.withdrawals.clear();
+        first..self.batches.len()
+    }
+
+    /// Mark a batch as verified once its root has been posted to L1. Returns false if the
+    /// posted root isn't the one of our batch with that index.
+    pub fn verify_batch(&mut self, batch: usize, root: FixedBytes<32>) -> bool {
+        match self.batches.get_mut(batch) {
+            Some(tree) if tree.root == root => tree.verified = true,
+            _ => return false,
+        }
+        for record in self.withdrawal_records.values_mut().flatten() {
+            if record.batch == Some(batch) && record.status == WithdrawalStatus::Batched {
+                record.status = WithdrawalStatus::Verified;
+            }
+        }
+        true
+    }
+
+    /// Mark a withdrawal as claimed once it has been withdrawn on L1. Returns false if there
+    /// is no such withdrawal.
+    pub fn claim_withdrawal(&mut self, batch: usize, index: usize, account: &AlloyAddress) -> bool {
+        let Some(record) = self
+            .withdrawal_records
+            .get_mut(account)
+            .and_then(|records| {
+                records
+                    .iter_mut()
+                    .find(|record| record.batch == Some(batch) && record.index == index)
+            })
+        else {
+            return false;
+        };
+        record.status = WithdrawalStatus::Claimed;
+        true
+    }
+
+    pub fn allowance(&self, asset: &Asset, owner: &AlloyAddress, spender: &AlloyAddress) -> U256 {
+        self.allowances
+            .get(asset)
+            .and_then(|owners| owners.get(owner))
+            .and_then(|spenders| spenders.get(spender))
+            .copied()
+            .unwrap_or(U256::ZERO)
+    }
+
+    pub fn approve(
+        &mut self,
+        asset: Asset,
+        owner: AlloyAddress,
+        spender: AlloyAddress,
+        amount: U256,
+    ) {
+        self.allowances
+            .entry(asset)
+            .or_default()
+            .entry(owner)
+            .or_default()
+            .insert(spender, amount);
+    }
+
+    /// Use up part of an allowance. As with ERC-20, an allowance of U256::MAX never decreases.
+    pub fn spend_allowance(
+        &mut self,
+        asset: Asset,
+        owner: AlloyAddress,
+        spender: AlloyAddress,
+        amount: U256,
+    ) -> Result<(), RollupError> {
+        let available = self.allowance(&asset, &owner, &spender);
+        if available == U256::MAX {
+            return Ok(());
+        }
+        let Some(remaining) = available.checked_sub(amount) else {
+            return Err(RollupError::InsufficientAllowance {
+                asset,
+                owner,
+                spender,
+                needed: amount,
+                available,
+            });
+        };
+        self.approve(asset, owner, spender, remaining);
+        Ok(())
+    }
+}
+
+/// Everything a batch of actions can change, so that a failed batch can be rolled back.
+/// Nonces, receipts and the L1 block are left out: batches never touch them.
+pub struct Snapshot<S> {
+    balances: BTreeMap<Asset, BTreeMap<AlloyAddress, U256>>,
+    allowances: BTreeMap<Asset, BTreeMap<AlloyAddress, BTreeMap<AlloyAddress, U256>>>,
+    session_keys: BTreeMap<AlloyAddress, SessionKey>,
+    accounts: BTreeMap<AlloyAddress, PolicyAccount>,
+    withdrawals: usize,
+    withdraw_trees: BTreeMap<Asset, WithdrawAccumulator>,
+    state: S,
+}
+
+impl<S: Clone, T, E> BaseRollupState<S, T, E> {
+    pub fn snapshot(&self) -> Snapshot<S> {
+        Snapshot {
+            balances: self.balances.clone(),
+            allowances: self.allowances.clone(),
+            session_keys: self.session_keys.clone(),
+            accounts: self.accounts.clone(),
+            withdrawals: self.withdrawals.len(),
+            withdraw_trees: self.withdraw_trees.clone(),
+            state: self.state.clone(),
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: Snapshot<S>) {
+        self.balances = snapshot.balances;
+        self.allowances = snapshot.allowances;
+        self.session_keys = snapshot.session_keys;
+        self.accounts = snapshot.accounts;
+        // withdrawals are recorded in the same order they are queued in
+        for (_, account, _) in self.withdrawals.drain(snapshot.withdrawals..) {
+            if let Some(records) = self.withdrawal_records.get_mut(&account) {
+                records.pop();
+            }
+        }
+        self.withdraw_trees = snapshot.withdraw_trees;
+        self.state = snapshot.state;
+    }
+}
+
+/// A session key signs transactions on behalf of `account` (e.g. a throwaway key kept in the
+/// browser, so that chess moves don't each need a wallet popup), but only until `expiry`, and
+/// only the transactions its `scope` allows. It is revoked when control of the account changes.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct SessionKey {
+    pub account: AlloyAddress,
+    pub expiry: ValidUntil,
+    pub scope: SessionScope,
+    /// the value of every action the key signed so far, which `scope.max_value` caps
+    #[serde(default)]
+    pub spent: U256,
+}
+
+/// What a session key may sign. Every action it signs (including each action of a batch) must
+/// be one of `actions` and be about `target` if one is set, and all of them together may move
+/// at most `max_value` if one is set. Session keys can never register or revoke session keys,
+/// or manage policy accounts.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct SessionScope {
+    pub actions: Vec<String>,
+    #[serde(default)]
+    pub target: Option<U256>,
+    #[serde(default)]
+    pub max_value: Option<U256>,
+}
+
+/// An action, described in the terms of a SessionScope: its name (the name of its variant, e.g.
+/// "Transfer" or "Move"), what it is about (e.g. a game id), and how much value it moves
+pub struct Action {
+    pub name: &'static str,
+    pub target: Option<U256>,
+    pub value: U256,
+}
+
+impl SessionScope {
+    /// Whether a key with this scope that already `spent` some value may sign `action`
+    pub fn allows(&self, action: &Action, spent: U256) -> bool {
+        self.actions.iter().any(|name| name == action.name)
+            && self
+                .target
+                .map_or(true, |target| action.target == Some(target))
+            && self.max_value.map_or(true, |max| {
+                spent
+                    .checked_add(action.value)
+                    .is_some_and(|total| total <= max)
+            })
+    }
+}
+
+/// The extension describes its own transactions to session key scopes, with access to the
+/// whole state (e.g. to look up the wager of the game a transaction is about)
+pub trait ScopedExtension<T> {
+    fn describe(&self, ext: &T) -> Action;
+}
+
+impl<S, T, E> BaseRollupState<S, T, E>
+where
+    Self: ScopedExtension<T>,
+{
+    /// What session key `key` spends on `data`, or OutOfScope if it may not sign it. The actions
+    /// of a batch are checked one by one as they are applied, so a batch itself spends nothing.
+    pub fn session_spend(
+        &self,
+        key: &AlloyAddress,
+        data: &TransactionData<T>,
+    ) -> Result<U256, RollupError> {
+        let Some(session_key) = self.session_keys.get(key) else {
+            return Err(RollupError::OutOfScope);
+        };
+        let action = match data {
+            TransactionData::BridgeTokens { .. }
+            | TransactionData::RegisterSessionKey { .. }
+            | TransactionData::RevokeSessionKey(_)
+            | TransactionData::CreateAccount { .. }
+            | TransactionData::SetPolicy(_)
+            | TransactionData::StartRecovery { .. }
+            | TransactionData::CancelRecovery
+            | TransactionData::FinishRecovery(_)
+            | TransactionData::SealWithdrawals => return Err(RollupError::OutOfScope),
+            TransactionData::Batch(_) => return Ok(U256::ZERO),
+            TransactionData::WithdrawTokens { amount, .. } => Action {
+                name: "WithdrawTokens",
+                target: None,
+                value: *amount,
+            },
+            TransactionData::Transfer { amount, .. } => Action {
+                name: "Transfer",
+                target: None,
+                value: *amount,
+            },
+            TransactionData::Approve { amount, .. } => Action {
+                name: "Approve",
+                target: None,
+                value: *amount,
+            },
+            TransactionData::TransferFrom { amount, .. } => Action {
+                name: "TransferFrom",
+                target: None,
+                value: *amount,
+            },
+            TransactionData::Extension(ext) => self.describe(ext),
+        };
+        if !session_key.scope.allows(&action, session_key.spent) {
+            return Err(RollupError::OutOfScope);
+        }
+        Ok(action.value)
+    }
+}
+
+impl<S, T: Serialize, E> BaseRollupState<S, T, E> {
+    /// Who signed for `stx.pub_key`: one of its session keys (which is returned, since what it
+    /// signs is limited to its scope), or without limits (None) the account itself, or for a
+    /// policy account, the signers its policy asks for
+    pub fn authorize(
+        &self,
+        stx: &SignedTransaction<T>,
+    ) -> Result<Option<AlloyAddress>, RollupError> {
+        let signer = stx.signer()?;
+        if let Some(session_key) = self.session_keys.get(&signer) {
+            if session_key.account == stx.pub_key && stx.cosigs.is_empty() {
+                if self.expired(&session_key.expiry) {
+                    return Err(RollupError::Expired(session_key.expiry));
+                }
+                return Ok(Some(signer));
+            }
+        }
+        if let Some(account) = self.accounts.get(&stx.pub_key) {
+            account.policy.authorize(&stx.account_signers()?)?;
+            return Ok(None);
+        }
+        if signer == stx.pub_key && stx.cosigs.is_empty() {
+            return Ok(None);
+        }
+        Err(RollupError::BadSignature)
+    }
+}
+
+/// There is no gas metering yet: every transaction costs a flat amount of gas, plus a little
+/// more for every event it emits
+pub const TX_GAS: u64 = 21_000;
+pub const EVENT_GAS: u64 = 1_000;
+
+pub fn gas_used<E>(events: &[Event<E>]) -> u64 {
+    let events = events
+        .iter()
+        .filter(|event| !matches!(event, Event::FeePaid { .. }))
+        .count();
+    TX_GAS + EVENT_GAS * events as u64
+}
+
+/// Every executed transaction produces a receipt, which can be looked up by transaction hash.
+/// `status` mirrors Ethereum receipts, but is always true for now: transactions that fail are
+/// rejected with a RollupError instead of being sequenced.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Receipt<E> {
+    pub tx_hash: FixedBytes<32>,
+    pub block_number: u64,
+    pub index: usize,
+    pub status: bool,
+    pub gas_used: u64,
+    pub events: Vec<Event<E>>,
+}
+
+/// A block moved out of the state by archive_blocks, with the receipts of its transactions
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ArchivedBlock<T, E> {
+    pub block: Block<T>,
+    pub receipts: Vec<Receipt<E>>,
+}
+
+impl<E: Serialize> Receipt<E> {
+    pub fn hash(&self) -> FixedBytes<32> {
+        keccak256(serde_json::to_vec(self).unwrap())
+    }
+}
+
+/// Events emitted by the execution engine. All rollups share the events for moving tokens around,
+/// and anything specific to the rollup (like chess moves) goes in the extension field.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum Event<E> {
+    Deposit {
+        asset: Asset,
+        account: AlloyAddress,
+        amount: U256,
+    },
+    Withdrawal {
+        asset: Asset,
+        account: AlloyAddress,
+        amount: U256,
+    },
+    /// the pending withdrawals of `asset` became batch number `batch`
+    BatchSealed {
+        asset: Asset,
+        batch: usize,
+        root: FixedBytes<32>,
+    },
+    Transfer {
+        asset: Asset,
+        from: AlloyAddress,
+        to: AlloyAddress,
+        amount: U256,
+    },
+    Approval {
+        asset: Asset,
+        owner: AlloyAddress,
+        spender: AlloyAddress,
+        amount: U256,
+    },
+    SessionKeyRegistered {
+        account: AlloyAddress,
+        key: AlloyAddress,
+        expiry: ValidUntil,
+    },
+    SessionKeyRevoked {
+        account: AlloyAddress,
+        key: AlloyAddress,
+    },
+    AccountCreated {
+        account: AlloyAddress,
+        creator: AlloyAddress,
+        policy: AccountPolicy,
+    },
+    /// the account's policy was set, or its guardian recovered it
+    PolicyChanged {
+        account: AlloyAddress,
+        policy: AccountPolicy,
+    },
+    RecoveryStarted {
+        account: AlloyAddress,
+        owner: AlloyAddress,
+        after: u64,
+    },
+    RecoveryCancelled {
+        account: AlloyAddress,
+    },
+    /// emitted last, and (unlike every other event) doesn't use any gas itself
+    FeePaid {
+        payer: AlloyAddress,
+        recipient: AlloyAddress,
+        amount: U256,
+    },
+    Extension(E),
+}
+
+impl<S, T: Serialize, E> BaseRollupState<S, T, E> {
+    /// Look up a sequenced transaction and its receipt by hash, whether it is sealed or not
+    pub fn transaction(
+        &self,
+        tx_hash: &FixedBytes<32>,
+    ) -> Option<(&SignedTransaction<T>, &Receipt<E>)> {
+        let receipt = self.receipts.get(tx_hash)?;
+        let transactions = if receipt.block_number == self.block_number() {
+            &self.sequenced
+        } else {
+            &self
+                .blocks
+                .iter()
+                .find(|block| block.header.number == receipt.block_number)?
+                .transactions
+        };
+        Some((transactions.get(receipt.index)?, receipt))
+    }
+
+    /// Move the sealed blocks up to and including `through` out of the state, along with the
+    /// receipts of their transactions (e.g. once they are proven, see the sequencer's archive).
+    /// Only their latest header is kept, for the blocks after them to chain to.
+    pub fn archive_blocks(&mut self, through: u64) -> Vec<ArchivedBlock<T, E>> {
+        let count = self
+            .blocks
+            .iter()
+            .take_while(|block| block.header.number <= through)
+            .count();
+        let mut archived = vec![];
+        for block in self.blocks.drain(..count) {
+            let receipts = block
+                .transactions
+                .iter()
+                .filter_map(|tx| self.receipts.remove(&tx.hash()))
+                .collect();
+            self.archived = Some(block.header.clone());
+            archived.push(ArchivedBlock { block, receipts });
+        }
+        archived
+    }
+
+    /// Charge the fee for a transaction that emitted `events`: to its sponsor if it has one,
+    /// otherwise to its sender. Returns the FeePaid event, if there was anything to pay.
+    pub fn charge_fee(
+        &mut self,
+        stx: &SignedTransaction<T>,
+        events: &[Event<E>],
+    ) -> Result<Option<Event<E>>, RollupError> {
+        let fee = U256::from(gas_used(events)) * self.fee_per_gas;
+        let payer = match &stx.sponsorship {
+            Some(sponsorship) if fee > sponsorship.max_fee => {
+                return Err(RollupError::FeeTooHigh {
+                    fee,
+                    max_fee: sponsorship.max_fee,
+                });
+            }
+            Some(sponsorship) => sponsorship.sponsor,
+            None => stx.pub_key,
+        };
+        if fee.is_zero() {
+            return Ok(None);
+        }
+        self.debit(GAS_TOKEN, payer, fee)?;
+        self.credit(GAS_TOKEN, self.fee_recipient, fee)?;
+        Ok(Some(Event::FeePaid {
+            payer,
+            recipient: self.fee_recipient,
+            amount: fee,
+        }))
+    }
+
+    /// Sequence a transaction that executed successfully, and store its receipt
+    pub fn record(&mut self, stx: SignedTransaction<T>, events: Vec<Event<E>>) -> FixedBytes<32> {
+        let tx_hash = stx.hash();
+        let receipt = Receipt {
+            tx_hash,
+            block_number: self.block_number(),
+            index: self.sequenced.len(),
+            status: true,
+            gas_used: gas_used(&events),
+            events,
+        };
+        self.receipts.insert(tx_hash, receipt);
+        self.sequenced.push(stx);
+        tx_hash
+    }
+}
+
+/// The ExecutionEngine is responsible for taking a transaction and applying it to the rollup state
+/// In this repo, we impl ExecutionEngine for FullRollupState
+/// The goal of this abstraction is to keep the `sequencer` as general as possible, so that it can
+/// execute arbitrary rollup code without knowing any specifics about the rollup
+/// ```rust,ignore
+/// impl ExecutionEngine<MyTransactions> for BaseRollupState<MyState, MyTransactions, MyEvents> {
+///     fn execute(&mut self, tx: SignedTransaction<MyTransactions>) -> Result<(), RollupError> {
+///         // implement your logic here
+///     }
+/// }
+/// ```
+///
+/// The EE is ignorant of all things related to kinode, http, etc. so that the same code can run
+/// inside the zkVM. Persistence and the RPC API live in the sequencer.
+pub trait ExecutionEngine<T> {
+    fn execute(&mut self, tx: SignedTransaction<T>) -> Result<(), RollupError>;
+}
+
+/// Where the funds of a withdrawal are: pending until the next batch, then in a batch whose root
+/// still has to be posted to L1, then verified (posted) so they can be claimed on L1, and finally
+/// claimed
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WithdrawalStatus {
+    Pending,
+    Batched,
+    Verified,
+    Claimed,
+}
+
+/// A withdrawal of `amount` of `asset`, made in `block`. `index` is its place in the withdrawal
+/// tree of its asset, which together with `batch` is how the bridge refers to it on L1.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct WithdrawalRecord {
+    pub asset: Asset,
+    pub amount: U256,
+    pub block: u64,
+    pub batch: Option<usize>,
+    pub index: usize,
+    pub status: WithdrawalStatus,
+}
+
+/// Withdrawal trees have a fixed depth, so a batch holds at most 2^32 withdrawals of an asset
+pub const WITHDRAW_TREE_DEPTH: usize = 32;
+
+/// To enable withdrawals, every withdrawal is appended to a Merkle tree of its asset as it
+/// happens. The tree only keeps one node per level, so it stays small no matter how many
+/// withdrawals it holds, and its root is always ready to be posted to L1 as a new batch.
+/// NOTE: this is the incremental Merkle tree of the eth2 deposit contract, but it hashes sorted
+/// pairs like the Uniswap MerkleDistributor contract, as the bridge (contracts/Bridge.sol) does
+/// when it verifies the claims on L1
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct WithdrawAccumulator {
+    /// at each level, the last left node on the path of the next leaf
+    branch: Vec<FixedBytes<32>>,
+    pub num_drops: usize,
+    pub token_total: U256,
+}
+
+impl WithdrawAccumulator {
+    /// Append a withdrawal of `asset`, returning its index in the tree. Fails, leaving the tree
+    /// as it was, if the tree's total would overflow.
+    pub fn push(
+        &mut self,
+        asset: Asset,
+        account: AlloyAddress,
+        amount: U256,
+    ) -> Result<usize, RollupError> {
+        let Some(token_total) = self.token_total.checked_add(amount) else {
+            return Err(RollupError::WithdrawalOverflow(asset));
+        };
+        if self.branch.is_empty() {
+            self.branch = vec![FixedBytes::ZERO; WITHDRAW_TREE_DEPTH];
+        }
+        let index = self.num_drops;
+        let mut node = WithdrawTree::to_node(U256::from(index), account, asset, amount);
+        let mut size = index + 1;
+        for level in 0..WITHDRAW_TREE_DEPTH {
+            if size % 2 == 1 {
+                self.branch[level] = node;
+                break;
+            }
+            node = WithdrawTree::sort_and_concat(self.branch[level], node);
+            size /= 2;
+        }
+        self.num_drops += 1;
+        self.token_total = token_total;
+        Ok(index)
+    }
+
+    /// The root of the tree, or zero (which no claim verifies against) if it is empty
+    pub fn root(&self) -> FixedBytes<32> {
+        if self.num_drops == 0 {
+            return FixedBytes::ZERO;
+        }
+        let zeros = WithdrawTree::zero_hashes();
+        let mut node = FixedBytes::ZERO;
+        let mut size = self.num_drops;
+        for level in 0..WITHDRAW_TREE_DEPTH {
+            node = if size % 2 == 1 {
+                WithdrawTree::sort_and_concat(self.branch[level], node)
+            } else {
+                WithdrawTree::sort_and_concat(node, zeros[level])
+            };
+            size /= 2;
+        }
+        node
+    }
+}
+
+/// A batch: the root of the withdrawal tree of an asset, as posted to L1 under the index of the
+/// batch. Only the root is kept in state; the withdrawals in it are stored by the sequencer,
+/// which generates the proofs that let users withdraw against the root when they ask for them.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct WithdrawTree {
+    pub asset: Asset,
+    pub root: FixedBytes<32>,
+    pub token_total: U256,
+    pub num_drops: usize,
+    pub verified: bool,
+    /// the claims of each account, for batches made before the sequencer kept their withdrawals
+    /// (which state from before versioning still has, see persistence)
+    #[serde(default)]
+    pub claims: Option<BTreeMap<AlloyAddress, Claim>>,
+}
+
+/// The information an adresss needs to withdraw their tokens
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Claim {
+    pub index: usize,
+    pub amount: U256,
+    pub proof: Vec<FixedBytes<32>>,
+}
+
+impl WithdrawTree {
+    /// The claim for the withdrawal at `index`, given all the withdrawals (account, amount) of
+    /// the batch of `asset`, in order
+    pub fn claim(
+        asset: Asset,
+        withdrawals: &[(AlloyAddress, U256)],
+        index: usize,
+    ) -> Option<Claim> {
+        let (_, amount) = withdrawals.get(index)?;
+        let zeros = Self::zero_hashes();
+        let mut layer: Vec<FixedBytes<32>> = withdrawals
+            .iter()
+            .enumerate()
+            .map(|(i, (account, amount))| Self::to_node(U256::from(i), *account, asset, *amount))
+            .collect();
+        let mut proof = Vec::with_capacity(WITHDRAW_TREE_DEPTH);
+        let mut node_idx = index;
+        for zero in zeros.iter().take(WITHDRAW_TREE_DEPTH) {
+            // past the last withdrawal, the tree is filled with empty subtrees
+            proof.push(layer.get(node_idx ^ 1).copied().unwrap_or(*zero));
+            layer = layer
+                .chunks(2)
+                .map(|pair| Self::sort_and_concat(pair[0], *pair.get(1).unwrap_or(zero)))
+                .collect();
+            node_idx /= 2;
+        }
+        Some(Claim {
+            index,
+            amount: *amount,
+            proof,
+        })
+    }
+
+    /// Check a claim the same way the bridge's withdraw does on L1: hash the leaf, then hash it
+    /// with each proof element in turn (sorted pairs) and compare to the root
+    pub fn verify_claim(
+        root: FixedBytes<32>,
+        asset: Asset,
+        account: AlloyAddress,
+        claim: &Claim,
+    ) -> bool {
+        let leaf = Self::to_node(U256::from(claim.index), account, asset, claim.amount);
+        let computed = claim
+            .proof
+            .iter()
+            .fold(leaf, |node, sibling| Self::sort_and_concat(node, *sibling));
+        computed == root
+    }
+
+    /// A withdrawal leaf commits to its asset, so that L1 can't pay a claim out in another one
+    fn to_node(index: U256, address: AlloyAddress, asset: Asset, amount: U256) -> FixedBytes<32> {
+        keccak256(
+            &Node {
+                index: U256::from(index),
+                account: address,
+                asset,
+                amount: amount,
+            }
+            .abi_encode_packed(),
+        )
+    }
+
+    /// The roots of empty subtrees of each height, from a single empty leaf up to the whole tree
+    fn zero_hashes() -> Vec<FixedBytes<32>> {
+        let mut zeros = vec![FixedBytes::ZERO];
+        for level in 0..WITHDRAW_TREE_DEPTH {
+            zeros.push(Self::sort_and_concat(zeros[level], zeros[level]));
+        }
+        zeros
+    }
+
+    fn sort_and_concat(first: FixedBytes<32>, second: FixedBytes<32>) -> FixedBytes<32> {
+        if first < second {
+            return keccak256(&[first, second].concat());
+        }
+        keccak256(&[second, first].concat())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::FullRollupState;
+    use crate::testing::*;
+    use proptest::prelude::*;
+
+    fn key(n: u64) -> FixedBytes<32> {
+        keccak256(n.to_be_bytes())
+    }
+
+    fn tree_of(size: u64) -> SparseMerkleTree {
+        SparseMerkleTree::new((0..size).map(|n| (key(n), key(n + 1_000))).collect())
+    }
+
+    #[test]
+    fn state_proofs_prove_every_leaf() {
+        for size in [1, 2, 3, 17, 64] {
+            let tree = tree_of(size);
+            let root = tree.root();
+            for n in 0..size {
+                let proof = tree.prove(&key(n));
+                assert!(verify_state_proof(root, key(n), key(n + 1_000), &proof));
+                assert!(!verify_state_proof(root, key(n), key(n + 2_000), &proof));
+                assert!(!verify_state_proof(root, key(n), FixedBytes::ZERO, &proof));
+            }
+        }
+    }
+
+    #[test]
+    fn state_proofs_prove_absent_keys() {
+        for size in [0, 1, 2, 3, 17, 64] {
+            let tree = tree_of(size);
+            let root = tree.root();
+            for n in 10_000..10_020 {
+                let proof = tree.prove(&key(n));
+                assert!(verify_state_proof(root, key(n), FixedBytes::ZERO, &proof));
+                assert!(!verify_state_proof(root, key(n), key(n), &proof));
+            }
+        }
+        // zero values aren't leaves at all
+        let with_zero = SparseMerkleTree::new(vec![(key(0), key(1)), (key(2), FixedBytes::ZERO)]);
+        assert_eq!(
+            with_zero.root(),
+            SparseMerkleTree::new(vec![(key(0), key(1))]).root()
+        );
+    }
+
+    #[test]
+    fn state_proofs_reject_tampering() {
+        let tree = tree_of(17);
+        let root = tree.root();
+        let proof = tree.prove(&key(3));
+        assert!(!verify_state_proof(
+            tree_of(16).root(),
+            key(3),
+            key(1_003),
+            &proof
+        ));
+        for depth in 0..proof.siblings.len() {
+            let mut tampered = proof.clone();
+            tampered.siblings[depth] = keccak256(tampered.siblings[depth]);
+            assert!(!verify_state_proof(root, key(3), key(1_003), &tampered));
+        }
+        // a leaf can't be proven absent by pointing at itself, or at another leaf off its path
+        let absent = tree.prove(&key(10_000));
+        let mut itself = absent.clone();
+        itself.leaf = Some((key(10_000), key(1)));
+        assert!(!verify_state_proof(
+            root,
+            key(10_000),
+            FixedBytes::ZERO,
+            &itself
+        ));
+        let present = StateProof {
+            siblings: proof.siblings.clone(),
+            leaf: Some((key(3), key(1_003))),
+        };
+        assert!(!verify_state_proof(
+            root,
+            key(3),
+            FixedBytes::ZERO,
+            &present
+        ));
+        for n in 0..17 {
+            let mut elsewhere = absent.clone();
+            elsewhere.leaf = Some((key(n), key(n + 1_000)));
+            if absent.leaf != elsewhere.leaf {
+                assert!(!verify_state_proof(
+                    root,
+                    key(10_000),
+                    FixedBytes::ZERO,
+                    &elsewhere
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn state_roots_dont_depend_on_order() {
+        let mut leaves: Vec<_> = (0..17).map(|n| (key(n), key(n + 1_000))).collect();
+        let root = SparseMerkleTree::new(leaves.clone()).root();
+        leaves.reverse();
+        assert_eq!(SparseMerkleTree::new(leaves).root(), root);
+        assert_eq!(tree_of(0).root(), FixedBytes::ZERO);
+    }
+
+    #[test]
+    fn transaction_hashes_cover_every_field() {
+        let data = TransactionData::WithdrawTokens {
+            asset: GAS_TOKEN,
+            amount: U256::from(1),
+        };
+        let stx = sign(1, transaction(0, data));
+        let mut expiring = stx.clone();
+        expiring.tx.valid_until = Some(ValidUntil::Timestamp(0));
+        let sponsored = sponsor(2, 0, stx.clone());
+        let cosigned = SignedTransaction {
+            cosigs: vec![personal_sign(2, "")],
+            ..stx.clone()
+        };
+        let hashes: std::collections::BTreeSet<_> = [&stx, &expiring, &sponsored, &cosigned]
+            .iter()
+            .map(|stx| stx.hash())
+            .collect();
+        assert_eq!(hashes.len(), 4);
+        assert_eq!(stx.clone().hash(), stx.hash());
+    }
+
+    #[test]
+    fn spending_an_allowance_reduces_it() {
+        let mut state = FullRollupState::default();
+        let (owner, spender) = (address(1), address(2));
+        state.approve(GAS_TOKEN, owner, spender, U256::from(30));
+        state
+            .spend_allowance(GAS_TOKEN, owner, spender, U256::from(20))
+            .unwrap();
+        assert_eq!(
+            state.allowance(&GAS_TOKEN, &owner, &spender),
+            U256::from(10)
+        );
+        assert_eq!(
+            state.spend_allowance(GAS_TOKEN, owner, spender, U256::from(11)),
+            Err(RollupError::InsufficientAllowance {
+                asset: GAS_TOKEN,
+                owner,
+                spender,
+                needed: U256::from(11),
+                available: U256::from(10),
+            })
+        );
+        state
+            .spend_allowance(GAS_TOKEN, owner, spender, U256::from(10))
+            .unwrap();
+        assert_eq!(state.allowance(&GAS_TOKEN, &owner, &spender), U256::ZERO);
+        // allowances are per asset
+        let token = AlloyAddress::repeat_byte(1);
+        assert_eq!(state.allowance(&token, &owner, &spender), U256::ZERO);
+    }
+
+    #[test]
+    fn unlimited_allowances_never_run_out() {
+        let mut state = FullRollupState::default();
+        let (owner, spender) = (address(1), address(2));
+        state.approve(GAS_TOKEN, owner, spender, U256::MAX);
+        for _ in 0..3 {
+            state
+                .spend_allowance(GAS_TOKEN, owner, spender, U256::MAX)
+                .unwrap();
+        }
+        assert_eq!(state.allowance(&GAS_TOKEN, &owner, &spender), U256::MAX);
+    }
+
+    #[test]
+    fn only_the_spender_uses_an_allowance() {
+        let mut state = FullRollupState::default();
+        let (owner, spender, other) = (address(1), address(2), address(3));
+        deposit(&mut state, owner, 100);
+        let approve = TransactionData::Approve {
+            asset: GAS_TOKEN,
+            spender,
+            amount: U256::from(50),
+        };
+        state.execute(sign(1, transaction(0, approve))).unwrap();
+
+        let transfer_from = TransactionData::TransferFrom {
+            asset: GAS_TOKEN,
+            from: owner,
+            to: other,
+            amount: U256::from(50),
+        };
+        assert_eq!(
+            state.execute(sign(3, transaction(0, transfer_from))),
+            Err(RollupError::InsufficientAllowance {
+                asset: GAS_TOKEN,
+                owner,
+                spender: other,
+                needed: U256::from(50),
+                available: U256::ZERO,
+            })
+        );
+        assert_eq!(balance(&state, owner), U256::from(100));
+        assert_eq!(
+            state.allowance(&GAS_TOKEN, &owner, &spender),
+            U256::from(50)
+        );
+    }
+
+    #[test]
+    fn withdrawals_that_overflow_the_total_are_refused() {
+        let mut tree = WithdrawAccumulator::default();
+        let account = AlloyAddress::repeat_byte(1);
+        assert_eq!(tree.push(GAS_TOKEN, account, U256::MAX), Ok(0));
+        let root = tree.root();
+        assert_eq!(
+            tree.push(GAS_TOKEN, account, U256::from(1)),
+            Err(RollupError::WithdrawalOverflow(GAS_TOKEN))
+        );
+        assert_eq!((tree.num_drops, tree.token_total), (1, U256::MAX));
+        assert_eq!(tree.root(), root);
+    }
+
+    fn withdrawals() -> impl Strategy<Value = Vec<(AlloyAddress, U256)>> {
+        prop::collection::vec((any::<[u8; 20]>(), any::<u128>()), 0..64).prop_map(|withdrawals| {
+            withdrawals
+                .into_iter()
+                .map(|(account, amount)| (AlloyAddress::from(account), U256::from(amount)))
+                .collect()
+        })
+    }
+
+    proptest! {
+        // every claim rebuilds the tree, so keep this quick in debug builds
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn every_claim_verifies_against_the_accumulated_root(withdrawals in withdrawals()) {
+            let mut tree = WithdrawAccumulator::default();
+            for (index, (account, amount)) in withdrawals.iter().enumerate() {
+                prop_assert_eq!(tree.push(GAS_TOKEN, *account, *amount), Ok(index));
+            }
+            prop_assert_eq!(tree.num_drops, withdrawals.len());
+            let root = tree.root();
+            for (index, (account, amount)) in withdrawals.iter().enumerate() {
+                let claim = WithdrawTree::claim(GAS_TOKEN, &withdrawals, index).unwrap();
+                prop_assert_eq!(claim.index, index);
+                prop_assert!(WithdrawTree::verify_claim(root, GAS_TOKEN, *account, &claim));
+                // and for nothing more than what was withdrawn, nor in another asset
+                let more = Claim {
+                    amount: amount + U256::from(1),
+                    ..claim.clone()
+                };
+                prop_assert!(!WithdrawTree::verify_claim(root, GAS_TOKEN, *account, &more));
+                let token = AlloyAddress::repeat_byte(1);
+                prop_assert!(!WithdrawTree::verify_claim(root, token, *account, &claim));
+            }
+            prop_assert!(WithdrawTree::claim(GAS_TOKEN, &withdrawals, withdrawals.len()).is_none());
+        }
+    }
+}
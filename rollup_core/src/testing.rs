@@ -0,0 +1,116 @@
+//! Keys, signatures and transactions for the tests
+use crate::engine::{ChessTransactions, FullRollupState};
+use crate::rollup_lib::{
+    ExecutionEngine, SignedTransaction, Sponsorship, Transaction, TransactionData, GAS_TOKEN,
+};
+use alloy_primitives::{eip191_hash_message, Address as AlloyAddress, Bytes, Signature, U256};
+use k256::ecdsa::SigningKey;
+use serde::Serialize;
+
+pub type Stx = SignedTransaction<ChessTransactions>;
+pub type Data = TransactionData<ChessTransactions>;
+
+/// Test key number `n` (any but 0)
+pub fn key(n: u8) -> SigningKey {
+    SigningKey::from_slice(&[n; 32]).unwrap()
+}
+
+pub fn address(n: u8) -> AlloyAddress {
+    AlloyAddress::from_public_key(key(n).verifying_key())
+}
+
+/// `message`, personal_signed by key `n`
+pub fn personal_sign(n: u8, message: &str) -> Signature {
+    let (sig, recovery_id) = key(n)
+        .sign_prehash_recoverable(eip191_hash_message(message).as_slice())
+        .unwrap();
+    Signature::from((sig, recovery_id))
+}
+
+pub fn transaction(nonce: u64, data: Data) -> Transaction<ChessTransactions> {
+    Transaction {
+        data,
+        nonce: U256::from(nonce),
+        valid_until: None,
+    }
+}
+
+/// `tx`, signed by key `n` for its own account
+pub fn sign(n: u8, tx: Transaction<ChessTransactions>) -> Stx {
+    sign_as(n, address(n), tx)
+}
+
+/// `tx`, signed by key `n` for `account`, e.g. as one of its session keys
+pub fn sign_as(n: u8, account: AlloyAddress, tx: Transaction<ChessTransactions>) -> Stx {
+    SignedTransaction {
+        pub_key: account,
+        sig: personal_sign(n, &serde_json::to_string(&tx).unwrap()),
+        tx,
+        sponsorship: None,
+        cosigs: vec![],
+    }
+}
+
+/// `tx`, signed for the policy account `account` by every key in `signers`
+pub fn sign_for(account: AlloyAddress, signers: &[u8], tx: Transaction<ChessTransactions>) -> Stx {
+    #[derive(Serialize)]
+    struct AccountMessage<'a> {
+        account: AlloyAddress,
+        tx: &'a Transaction<ChessTransactions>,
+    }
+    let message = serde_json::to_string(&AccountMessage { account, tx: &tx }).unwrap();
+    let mut sigs = signers.iter().map(|n| personal_sign(*n, &message));
+    SignedTransaction {
+        pub_key: account,
+        sig: sigs.next().unwrap(),
+        tx,
+        sponsorship: None,
+        cosigs: sigs.collect(),
+    }
+}
+
+/// `stx`, with its fee paid by key `n` up to `max_fee`
+pub fn sponsor(n: u8, max_fee: u64, stx: Stx) -> Stx {
+    #[derive(Serialize)]
+    struct SponsorMessage {
+        sig: Bytes,
+        max_fee: U256,
+    }
+    let max_fee = U256::from(max_fee);
+    let message = serde_json::to_string(&SponsorMessage {
+        sig: Bytes::from(stx.sig.as_bytes().to_vec()),
+        max_fee,
+    })
+    .unwrap();
+    SignedTransaction {
+        sponsorship: Some(Sponsorship {
+            sponsor: address(n),
+            max_fee,
+            sig: personal_sign(n, &message),
+        }),
+        ..stx
+    }
+}
+
+/// Bridge `amount` of the gas token to `account`
+pub fn deposit(state: &mut FullRollupState, account: AlloyAddress, amount: u64) {
+    let deposit = SignedTransaction {
+        pub_key: account,
+        sig: Signature::test_signature(),
+        tx: transaction(
+            state.sequenced.len() as u64,
+            TransactionData::BridgeTokens {
+                asset: GAS_TOKEN,
+                amount: U256::from(amount),
+                block: U256::ZERO,
+            },
+        ),
+        sponsorship: None,
+        cosigs: vec![],
+    };
+    state.execute(deposit).unwrap();
+}
+
+pub fn balance(state: &FullRollupState, account: AlloyAddress) -> U256 {
+    state.balance_of(&GAS_TOKEN, &account)
+}
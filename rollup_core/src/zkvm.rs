@@ -0,0 +1,43 @@
+use crate::engine::{ChessTransactions, FullRollupState};
+use crate::rollup_lib::Block;
+use alloy_primitives::FixedBytes;
+
+/// The body of the zkVM program: replay the blocks the sequencer sealed, and commit to the state
+/// before and after them. The public values are, in order: the old state root, the hash of the
+/// block before the first one (if any), the new state root, the hash of the last block, the
+/// genesis hash, and what the program was built for (see crate::built_for).
+/// NOTE: reads its input in the order `prover_types::prover_input` writes it
+pub fn prove_blocks(genesis_hash: FixedBytes<32>, built_for: &str) {
+    assert_eq!(
+        built_for,
+        crate::built_for(genesis_hash),
+        "built for another engine"
+    );
+    // read in the old state, which has to descend from the genesis this program was built for
+    let mut state = sp1_zkvm::io::read::<FullRollupState>();
+    assert_eq!(
+        state.genesis_hash, genesis_hash,
+        "state from another genesis"
+    );
+    // read in the next blocks of transactions
+    let blocks = sp1_zkvm::io::read::<Vec<Block<ChessTransactions>>>();
+
+    // commit to where the blocks start from: the state they are replayed on, and the block they
+    // follow. A proof then only chains onto the one whose new state and last block these are.
+    sp1_zkvm::io::write(&state.state_root());
+    sp1_zkvm::io::write(&state.latest_header().map(|header| header.hash()));
+
+    // execute each block, checking it against the header the sequencer sealed
+    for block in blocks.iter() {
+        state.replay_block(block).unwrap();
+    }
+
+    // commit to the new state: the L1 verifier and light clients check state claims against it
+    sp1_zkvm::io::write(&state.state_root());
+    // and to the last block, which links back to every block before it through its parent hash
+    sp1_zkvm::io::write(&state.latest_header().map(|header| header.hash()));
+    // and to the genesis, so the proof can't be taken for one of another rollup
+    sp1_zkvm::io::write(&genesis_hash);
+    // and to the engine that replayed them
+    sp1_zkvm::io::write(&built_for);
+}
@@ -0,0 +1,222 @@
+use alloy_primitives::FixedBytes;
+use kinode_process_lib::{
+    vfs::{create_drive, open_dir, open_file},
+    Address,
+};
+use rollup_core::engine::{ChessEvents, ChessTransactions, FullRollupState};
+use rollup_core::persistence::Persistence;
+use rollup_core::rollup_lib::{ArchivedBlock, Block, Receipt, SignedTransaction};
+use std::collections::BTreeMap;
+
+/// The vfs drive holding proven blocks once they are moved out of the state
+const ARCHIVE_DRIVE: &str = "archive";
+/// The state at the latest archived block, which the next proof starts from
+const BASE_FILE: &str = "base";
+/// The state the proof being made ends at, until the proof comes back
+const PROVING_FILE: &str = "proving";
+
+type Archived = ArchivedBlock<ChessTransactions, ChessEvents>;
+
+/// Proven blocks don't need to be kept in the state (which is saved all the time, and sent to
+/// the prover): once a proof comes back, the blocks it covers are moved into the archive, one
+/// `blocks-{first}-{last}.json` file per proof, and the rpc reads them from there. Archived
+/// transactions are found by hash through `index-{xx}.jsonl` files: one line (hash, block
+/// number) for every transaction whose hash starts with the byte xx.
+pub struct Archive {
+    drive: String,
+}
+
+impl Archive {
+    pub fn open(our: &Address) -> anyhow::Result<Self> {
+        Ok(Self {
+            drive: create_drive(our.package_id(), ARCHIVE_DRIVE, Some(5))?,
+        })
+    }
+
+    /// The state the next proof starts from: the state at the latest archived block, or None
+    /// if nothing has been archived yet (so the next proof starts from genesis)
+    pub fn base(&self) -> anyhow::Result<Option<FullRollupState>> {
+        match self.read(BASE_FILE)? {
+            Some(bytes) => FullRollupState::decode(&bytes).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Whether a proof is being made (i.e. start_proof was called and the proof hasn't come back)
+    pub fn proving(&self) -> anyhow::Result<bool> {
+        Ok(self.read(PROVING_FILE)?.is_some())
+    }
+
+    /// Keep the state a proof is being made up to: every sealed block in it is being proven,
+    /// so nothing may be sequenced into its open block
+    pub fn start_proof(&self, state: &FullRollupState) -> anyhow::Result<()> {
+        if !state.sequenced.is_empty() {
+            return Err(anyhow::anyhow!("can't prove a state with an open block"));
+        }
+        self.write(PROVING_FILE, &state.encode()?)
+    }
+
+    /// The proof was dropped (e.g. the prover disconnected), nothing gets archived
+    pub fn cancel_proof(&self) -> anyhow::Result<()> {
+        self.write(PROVING_FILE, &[])
+    }
+
+    /// The proof from start_proof came back: archive the blocks it covers out of `state`, and
+    /// keep the state it ended at (without those blocks) as the base of the next proof.
+    /// Returns the last archived block, if there was anything to archive.
+    pub fn finish_proof(&self, state: &mut FullRollupState) -> anyhow::Result<Option<u64>> {
+        let Some(bytes) = self.read(PROVING_FILE)? else {
+            return Err(anyhow::anyhow!("got a proof that wasn't asked for"));
+        };
+        let mut proven = FullRollupState::decode(&bytes)?;
+        let through = proven.latest_header().map(|header| header.number);
+        if let Some(through) = through {
+            proven.archive_blocks(through);
+            self.keep(&state.archive_blocks(through))?;
+        }
+        self.write(BASE_FILE, &proven.encode()?)?;
+        self.write(PROVING_FILE, &[])?;
+        Ok(through)
+    }
+
+    /// Start proving from `state` as it is: archive its sealed blocks without a proof, and make
+    /// it the base of the next proof. Only for rollups from before genesis files, whose blocks
+    /// have no state before them to be replayed on.
+    pub fn rebase(&self, state: &mut FullRollupState) -> anyhow::Result<()> {
+        if !state.sequenced.is_empty() {
+            return Err(anyhow::anyhow!(
+                "can't prove from a state with an open block"
+            ));
+        }
+        if let Some(last) = state.blocks.last().map(|block| block.header.number) {
+            self.keep(&state.archive_blocks(last))?;
+        }
+        self.write(BASE_FILE, &state.encode()?)?;
+        self.write(PROVING_FILE, &[])
+    }
+
+    /// Write out blocks archived out of the state, and index their transactions
+    fn keep(&self, archived: &[Archived]) -> anyhow::Result<()> {
+        let (Some(first), Some(last)) = (archived.first(), archived.last()) else {
+            return Ok(());
+        };
+        let name = format!(
+            "blocks-{}-{}.json",
+            first.block.header.number, last.block.header.number
+        );
+        self.write(&name, &serde_json::to_vec(archived)?)?;
+        self.index(archived)
+    }
+
+    /// An archived transaction and its receipt, by hash
+    pub fn transaction(
+        &self,
+        hash: &FixedBytes<32>,
+    ) -> anyhow::Result<Option<(SignedTransaction<ChessTransactions>, Receipt<ChessEvents>)>> {
+        let Some(lines) = self.read(&index_name(hash))? else {
+            return Ok(None);
+        };
+        let mut number = None;
+        for line in lines
+            .split(|byte| *byte == b'\n')
+            .filter(|line| !line.is_empty())
+        {
+            let (indexed, block): (FixedBytes<32>, u64) = serde_json::from_slice(line)?;
+            if indexed == *hash {
+                number = Some(block);
+                break;
+            }
+        }
+        let Some(archived) = number
+            .map(|number| self.archived(number))
+            .transpose()?
+            .flatten()
+        else {
+            return Ok(None);
+        };
+        let Some(receipt) = archived.receipts.into_iter().find(|r| r.tx_hash == *hash) else {
+            return Ok(None);
+        };
+        let mut transactions = archived.block.transactions;
+        if receipt.index >= transactions.len() {
+            return Ok(None);
+        }
+        Ok(Some((transactions.swap_remove(receipt.index), receipt)))
+    }
+
+    /// An archived block, by number
+    pub fn block(&self, number: u64) -> anyhow::Result<Option<Block<ChessTransactions>>> {
+        Ok(self.archived(number)?.map(|archived| archived.block))
+    }
+
+    /// An archived block with its receipts, by number
+    fn archived(&self, number: u64) -> anyhow::Result<Option<Archived>> {
+        for (first, last, name) in self.files()? {
+            if (first..=last).contains(&number) {
+                return Ok(self
+                    .blocks(&name)?
+                    .into_iter()
+                    .find(|archived| archived.block.header.number == number));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Add the transactions of newly archived blocks to the index
+    fn index(&self, archived: &[Archived]) -> anyhow::Result<()> {
+        let mut lines: BTreeMap<String, Vec<u8>> = BTreeMap::new();
+        for archived in archived {
+            for receipt in archived.receipts.iter() {
+                let lines = lines.entry(index_name(&receipt.tx_hash)).or_default();
+                serde_json::to_writer(
+                    &mut *lines,
+                    &(receipt.tx_hash, archived.block.header.number),
+                )?;
+                lines.push(b'\n');
+            }
+        }
+        for (name, lines) in lines {
+            open_file(&self.path(&name), true, Some(5))?.append(&lines)?;
+        }
+        Ok(())
+    }
+
+    /// The archive files, with the first and last block in each, oldest first
+    fn files(&self) -> anyhow::Result<Vec<(u64, u64, String)>> {
+        let mut files: Vec<(u64, u64, String)> = open_dir(&self.drive, false, Some(5))?
+            .read()?
+            .iter()
+            .filter_map(|entry| {
+                let name = entry.path.rsplit('/').next()?;
+                let range = name.strip_prefix("blocks-")?.strip_suffix(".json")?;
+                let (first, last) = range.split_once('-')?;
+                Some((first.parse().ok()?, last.parse().ok()?, name.to_string()))
+            })
+            .collect();
+        files.sort();
+        Ok(files)
+    }
+
+    fn blocks(&self, name: &str) -> anyhow::Result<Vec<Archived>> {
+        let file = open_file(&self.path(name), false, Some(5))?;
+        Ok(serde_json::from_slice(&file.read()?)?)
+    }
+
+    fn read(&self, name: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        let file = open_file(&self.path(name), true, Some(5))?;
+        let bytes = file.read()?;
+        Ok(if bytes.is_empty() { None } else { Some(bytes) })
+    }
+
+    fn write(&self, name: &str, bytes: &[u8]) -> anyhow::Result<()> {
+        open_file(&self.path(name), true, Some(5))?.write(bytes)
+    }
+
+    fn path(&self, name: &str) -> String {
+        format!("{}/{}", self.drive, name)
+    }
+}
+
+fn index_name(hash: &FixedBytes<32>) -> String {
+    format!("index-{:02x}.jsonl", hash[0])
+}
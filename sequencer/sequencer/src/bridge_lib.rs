@@ -1,29 +1,31 @@
+use crate::wal::{Wal, WalEntry};
 use crate::{
-    BaseRollupState, ExecutionEngine, FullRollupState, SignedTransaction, Transaction,
-    TransactionData,
+    Asset, BaseRollupState, ExecutionEngine, FullRollupState, SignedTransaction, Transaction,
+    TransactionData, GAS_TOKEN,
 };
-use alloy_primitives::{Signature, U256};
+use alloy_primitives::{Address as AlloyAddress, Signature, U256};
 use alloy_sol_types::{sol, SolEvent};
 use kinode_process_lib::eth;
 use kinode_process_lib::println;
 
 sol! {
     event Deposit(address sender, uint256 amount);
+    event TokenDeposit(address sender, address token, uint256 amount);
     event BatchPosted(uint256 withdrawRootIndex, bytes32 withdrawRoot);
+    event Withdraw(uint256 withdrawRootIndex, uint256 index, address account, address asset, uint256 amount);
 }
 
-pub fn subscribe_to_logs(eth_provider: &eth::Provider, from_block: U256) {
+/// Subscribe to the logs of the `bridge` contract, from the L1 block after `from_block`
+pub fn subscribe_to_logs(eth_provider: &eth::Provider, bridge: AlloyAddress, from_block: U256) {
     let filter = eth::Filter::new()
-        .address(
-            "0x24E063a827CB134315aC57A380446c8bF5418555"
-                .parse::<eth::Address>()
-                .unwrap(),
-        )
+        .address(bridge)
         .from_block(from_block.to::<u64>() + 1)
         .to_block(eth::BlockNumberOrTag::Latest)
         .events(vec![
             "Deposit(address,uint256)",
+            "TokenDeposit(address,address,uint256)",
             "BatchPosted(uint256,bytes32)",
+            "Withdraw(uint256,uint256,address,address,uint256)",
         ]);
 
     loop {
@@ -40,26 +42,27 @@ pub fn subscribe_to_logs(eth_provider: &eth::Provider, from_block: U256) {
 }
 
 /// TODO this needs to include a from_block parameter because we don't want to reprocess
-pub fn get_old_logs(eth_provider: &eth::Provider, state: &mut FullRollupState) {
+pub fn get_old_logs(eth_provider: &eth::Provider, state: &mut FullRollupState, wal: &mut Wal) {
     let filter = eth::Filter::new()
-        .address(
-            "0x24E063a827CB134315aC57A380446c8bF5418555"
-                .parse::<eth::Address>()
-                .unwrap(),
-        )
+        .address(state.bridge)
         .from_block(state.l1_block.to::<u64>() + 1)
         .to_block(eth::BlockNumberOrTag::Latest)
         .events(vec![
             "Deposit(address,uint256)",
+            "TokenDeposit(address,address,uint256)",
             "BatchPosted(uint256,bytes32)",
+            "Withdraw(uint256,uint256,address,address,uint256)",
         ]);
     loop {
         match eth_provider.get_logs(&filter) {
             Ok(logs) => {
                 for log in logs {
-                    match handle_log(state, &log) {
-                        Ok(()) => continue,
-                        Err(e) => println!("error handling log: {:?}", e),
+                    let logged = handle_log(state, &log).and_then(|entry| match entry {
+                        Some(entry) => wal.log(state, entry),
+                        None => Ok(()),
+                    });
+                    if let Err(e) = logged {
+                        println!("error handling log: {:?}", e);
                     }
                 }
                 break;
@@ -73,9 +76,14 @@ pub fn get_old_logs(eth_provider: &eth::Provider, state: &mut FullRollupState) {
     }
 }
 
-pub fn handle_log<S, T>(state: &mut BaseRollupState<S, T>, log: &eth::Log) -> anyhow::Result<()>
+/// Apply a bridge log to the state. Deposits are sequenced as transactions; any other change
+/// is returned, for the write-ahead log.
+pub fn handle_log<S, T, E>(
+    state: &mut BaseRollupState<S, T, E>,
+    log: &eth::Log,
+) -> anyhow::Result<Option<WalEntry>>
 where
-    BaseRollupState<S, T>: ExecutionEngine<T>,
+    BaseRollupState<S, T, E>: ExecutionEngine<T>,
 {
     match log.topics[0] {
         Deposit::SIGNATURE_HASH => {
@@ -84,34 +92,81 @@ where
             let sender = deposit.0;
             let amount = deposit.1;
 
-            state.execute(SignedTransaction {
-                pub_key: sender,
-                sig: Signature::test_signature(), // NOTE: deposit txs are unsigned (TODO should be a null sig)
-                tx: Transaction {
-                    nonce: U256::ZERO, // NOTE: this doesn't need to be a "real" nonce since deposits are ex-nihilo
-                    data: TransactionData::BridgeTokens {
-                        amount,
-                        block: log.block_number.unwrap(),
-                    },
-                },
-            })?;
+            state.execute(bridge_transaction(sender, GAS_TOKEN, amount, log))?;
+        }
+        TokenDeposit::SIGNATURE_HASH => {
+            println!("token deposit event");
+            let deposit = TokenDeposit::abi_decode_data(&log.data, true).unwrap();
+            let sender = deposit.0;
+            let token = deposit.1;
+            let amount = deposit.2;
+
+            state.execute(bridge_transaction(sender, token, amount, log))?;
         }
         BatchPosted::SIGNATURE_HASH => {
             let batch = BatchPosted::abi_decode_data(&log.data, true).unwrap();
             let index: usize = batch.0.to::<usize>();
             let root = batch.1;
 
-            if state.batches.len() > index && state.batches[index].root == root {
-                state.batches[index].verified = true;
-                return Ok(());
-            } else {
+            if !state.verify_batch(index, root) {
                 // If this ever happens, it means the sequencer is in an inconsistent state with the chain
                 println!("sequencer: critical error, state out of sync with chain");
+                return Ok(None);
+            }
+            return Ok(Some(WalEntry::VerifyBatch { batch: index, root }));
+        }
+        Withdraw::SIGNATURE_HASH => {
+            let withdrawal = Withdraw::abi_decode_data(&log.data, true).unwrap();
+            let batch: usize = withdrawal.0.to::<usize>();
+            let index: usize = withdrawal.1.to::<usize>();
+            let account = withdrawal.2;
+            let asset = withdrawal.3;
+
+            if state.batches.get(batch).map(|batch| batch.asset) != Some(asset) {
+                println!(
+                    "sequencer: claimed withdrawal {index} of batch {batch} is of another asset"
+                );
+                return Ok(None);
+            }
+            if !state.claim_withdrawal(batch, index, &account) {
+                println!("sequencer: claimed withdrawal {index} of batch {batch} is unknown");
+                return Ok(None);
             }
+            return Ok(Some(WalEntry::ClaimWithdrawal {
+                batch,
+                index,
+                account,
+            }));
         }
         _ => {
             return Err(anyhow::anyhow!("unknown event"));
         }
     }
-    Ok(())
+    Ok(None)
+}
+
+/// Deposits are sequenced as (unsigned) BridgeTokens transactions crediting the sender
+fn bridge_transaction<T>(
+    sender: AlloyAddress,
+    asset: Asset,
+    amount: U256,
+    log: &eth::Log,
+) -> SignedTransaction<T> {
+    SignedTransaction {
+        pub_key: sender,
+        sig: Signature::test_signature(), // NOTE: deposit txs are unsigned (TODO should be a null sig)
+        tx: Transaction {
+            // NOTE: this doesn't need to be a "real" nonce since deposits are ex-nihilo, but using the
+            // L1 log index makes sure that two otherwise identical deposits have different hashes
+            nonce: log.log_index.unwrap_or_default(),
+            data: TransactionData::BridgeTokens {
+                asset,
+                amount,
+                block: log.block_number.unwrap(),
+            },
+            valid_until: None,
+        },
+        sponsorship: None,
+        cosigs: vec![],
+    }
 }
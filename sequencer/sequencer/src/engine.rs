@@ -1 +0,0 @@
-../../../elf_program/src/engine.rs
\ No newline at end of file
@@ -2,28 +2,44 @@
 use kinode_process_lib::eth;
 use kinode_process_lib::kernel_types::MessageType;
 use kinode_process_lib::{
-    await_message, call_init, get_blob, http, println,
+    await_message, call_init, get_blob, http, println, timer,
     vfs::{create_drive, create_file},
     Address, Message, Request,
 };
 use serde::{Deserialize, Serialize};
-use sp1_core::SP1Stdin;
 
+mod archive;
+use archive::Archive;
 mod bridge_lib;
 use bridge_lib::{get_old_logs, handle_log, subscribe_to_logs};
-mod engine;
-use engine::FullRollupState;
-mod prover_types;
-use prover_types::ProveRequest;
-mod rollup_lib;
-use rollup_lib::*;
+mod mempool;
+use mempool::Mempool;
+mod wal;
+use alloy_primitives::{Address as AlloyAddress, FixedBytes, Signature, U256};
+use rollup_core::engine::{ChessState, ChessTransactions, FullRollupState};
+use rollup_core::genesis::Genesis;
+use rollup_core::persistence::{set_codec, set_l1_settings, Codec, Persistence};
+use rollup_core::prover_types::{prover_input, ProveRequest};
+use rollup_core::rollup_lib::*;
+use wal::{Wal, WalEntry};
+mod rpc_api;
+use rpc_api::RpcApi;
 
 const ELF: &[u8] = include_bytes!("../../../elf_program/elf/riscv32im-succinct-zkvm-elf");
+/// The state the rollup starts from on first boot
+const GENESIS: &str = include_str!("../../../genesis.json");
+/// A block is sealed every BLOCK_TIME_MS, or as soon as it holds MAX_BLOCK_TRANSACTIONS
+const BLOCK_TIME_MS: u64 = 5_000;
+const MAX_BLOCK_TRANSACTIONS: usize = 100;
+/// The vfs drive holding the withdrawals of every batch, one file per batch
+const BATCHES_DRIVE: &str = "batches";
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 enum AdminActions {
     Prove,
     BatchWithdrawals,
+    /// re-save the state with another codec, e.g. Json to edit it by hand and Bincode after
+    SetStateCodec(Codec),
 }
 
 // Boilerplate: generate the wasm bindings for a process
@@ -53,23 +69,79 @@ fn initialize(our: Address) {
     // This lets the optional prover_extension connect to us.
     http::bind_ext_path("/").unwrap();
 
-    // Grab our state
-    let mut state = FullRollupState::load();
+    // the ELF only proves blocks of the engine and from the genesis it was built for
+    let genesis = genesis();
+    if !elf_built_for(&genesis) {
+        panic!(
+            "{}: the ELF was built from a different rollup-core or genesis.json, rebuild it with \
+             `cargo prove build`",
+            our.package()
+        );
+    }
+
+    // Grab our state, or start from genesis on first boot. If it can't be read (e.g. it was
+    // saved by a newer version of the sequencer), stop here: starting over from genesis would
+    // wipe every balance. State from before genesis files settles where genesis.json says.
+    set_l1_settings(genesis.l1_chain_id, genesis.bridge);
+    let state = match FullRollupState::load() {
+        Ok(Some(state)) => state,
+        Ok(None) => FullRollupState::from_genesis(&genesis),
+        Err(e) => panic!("{}: failed to load state: {e:?}", our.package()),
+    };
+    // (rollups that started before genesis files have no genesis hash to check)
+    if state.genesis_hash != FixedBytes::ZERO && state.genesis_hash != genesis.hash() {
+        panic!(
+            "{}: the saved state started from a different genesis.json",
+            our.package()
+        );
+    }
+    // the write-ahead log has the latest state, unless its last entries didn't make it
+    let (mut wal, mut state) = match Wal::open(&our, state) {
+        Ok(opened) => opened,
+        Err(e) => panic!("{}: failed to open write-ahead log: {e:?}", our.package()),
+    };
+    // rollups from before genesis files have no genesis for the ELF to prove their blocks from
+    if state.genesis_hash == FixedBytes::ZERO {
+        if let Err(e) = adopt_genesis(&our, &genesis, &mut state, &mut wal) {
+            panic!(
+                "{}: can't prove this rollup from genesis.json: {e:?}",
+                our.package()
+            );
+        }
+    }
+    // if no transactions made it into the open block, it only starts now
+    if state.sequenced.is_empty() {
+        state.timestamp = now();
+    }
 
     // create a new eth provider to read logs from chain (deposits and state root updates)
-    let eth_provider = eth::Provider::new(10, 5);
+    let eth_provider = eth::Provider::new(state.l1_chain_id, 5);
 
     // index all old deposits
-    get_old_logs(&eth_provider, &mut state);
-    state.save();
+    get_old_logs(&eth_provider, &mut state, &mut wal);
+    wal.sync(&state).unwrap();
     // subscribe to new deposits
-    subscribe_to_logs(&eth_provider, state.l1_block);
+    subscribe_to_logs(&eth_provider, state.bridge, state.l1_block);
+    // start the block timer
+    timer::set_timer(BLOCK_TIME_MS, None);
 
     // enter the main event loop
-    main_loop(&our, &mut state, &mut None);
+    main_loop(
+        &our,
+        &mut state,
+        &mut wal,
+        &mut Mempool::default(),
+        &mut None,
+    );
 }
 
-fn main_loop(our: &Address, state: &mut FullRollupState, connection: &mut Option<u32>) {
+fn main_loop(
+    our: &Address,
+    state: &mut FullRollupState,
+    wal: &mut Wal,
+    mempool: &mut Mempool<ChessTransactions>,
+    connection: &mut Option<u32>,
+) {
     loop {
         // Call await_message() to wait for any incoming messages.
         // If we get a network error, make a print and throw it away.
@@ -79,7 +151,7 @@ fn main_loop(our: &Address, state: &mut FullRollupState, connection: &mut Option
                 println!("{our}: got network error: {send_error:?}");
                 continue;
             }
-            Ok(message) => match handle_message(&our, &message, state, connection) {
+            Ok(message) => match handle_message(&our, &message, state, wal, mempool, connection) {
                 Ok(()) => continue,
                 Err(e) => println!("{our}: error handling request: {:?}", e),
             },
@@ -92,10 +164,20 @@ fn handle_message(
     our: &Address,
     message: &Message,
     state: &mut FullRollupState,
+    wal: &mut Wal,
+    mempool: &mut Mempool<ChessTransactions>,
     connection: &mut Option<u32>,
 ) -> anyhow::Result<()> {
-    // no responses
+    // the only responses we care about are from the block timer
     if !message.is_request() {
+        if message.source().node == our.node
+            && message.source().process.to_string() == "timer:distro:sys"
+        {
+            seal_block(state)?;
+            mempool.evict(state, now());
+            timer::set_timer(BLOCK_TIME_MS, None);
+            wal.sync(state)?;
+        }
         return Ok(());
     }
     if message.source().node != our.node {
@@ -104,9 +186,9 @@ fn handle_message(
         // - then sequence it
         return Ok(());
     }
-    return match message.source().process.to_string().as_str() {
+    let result = match message.source().process.to_string().as_str() {
         "http_server:distro:sys" => {
-            return handle_http_request(our, state, connection, message);
+            handle_http_request(our, state, wal, mempool, connection, message)
         }
         "eth:distro:sys" => {
             // we need to first extract the log
@@ -120,16 +202,113 @@ fn handle_message(
             };
             // then we handle the log with the standard bridge_lib::handle_log
             // which implements the default, audited way to interact with deposits
-            handle_log(state, &log)
+            match handle_log(state, &log)? {
+                Some(entry) => wal.log(state, entry),
+                None => Ok(()),
+            }
         }
-        _ => handle_admin_message(&our, message, state, connection),
+        _ => handle_admin_message(&our, message, state, wal, connection),
     };
+    // don't wait for the timer if the block is already full
+    if state.sequenced.len() >= MAX_BLOCK_TRANSACTIONS {
+        seal_block(state)?;
+    }
+    // whatever this message changed goes into the write-ahead log
+    wal.sync(state)?;
+    result
+}
+
+/// Seal all sequenced transactions into a new block (which the caller logs, see Wal::sync)
+fn seal_block(state: &mut FullRollupState) -> anyhow::Result<()> {
+    if state.sequenced.is_empty() {
+        // don't produce empty blocks, the open block just starts later
+        state.timestamp = now();
+        return Ok(());
+    }
+    let block = state.seal_block(now());
+    println!(
+        "sequencer: sealed block {} with {} transactions",
+        block.header.number,
+        block.transactions.len()
+    );
+    Ok(())
+}
+
+/// Withdrawals are sealed into batches by an (unsigned) SealWithdrawals transaction, numbered
+/// by the first batch it makes
+fn seal_transaction(first_batch: usize) -> SignedTransaction<ChessTransactions> {
+    SignedTransaction {
+        pub_key: AlloyAddress::ZERO,
+        sig: Signature::test_signature(),
+        tx: Transaction {
+            data: TransactionData::SealWithdrawals,
+            nonce: U256::from(first_batch),
+            valid_until: None,
+        },
+        sponsorship: None,
+        cosigs: vec![],
+    }
+}
+
+/// Make a rollup from before genesis files provable from here on. The blocks it sealed so far
+/// can't be proven (there is no state before them to replay them on), so they are archived
+/// without a proof, and its state as it is now, under the hash of genesis.json, is what the
+/// first proof starts from. Its L1 chain and bridge have to be the ones in genesis.json.
+fn adopt_genesis(
+    our: &Address,
+    genesis: &Genesis<ChessState>,
+    state: &mut FullRollupState,
+    wal: &mut Wal,
+) -> anyhow::Result<()> {
+    if (state.l1_chain_id, state.bridge) != (genesis.l1_chain_id, genesis.bridge) {
+        return Err(anyhow::anyhow!(
+            "it settles on chain {} at {}, but genesis.json on chain {} at {}",
+            state.l1_chain_id,
+            state.bridge,
+            genesis.l1_chain_id,
+            genesis.bridge
+        ));
+    }
+    if !state.sequenced.is_empty() {
+        state.seal_block(now());
+    }
+    state.genesis_hash = genesis.hash();
+    Archive::open(our)?.rebase(state)?;
+    println!(
+        "{}: blocks before {} are archived unproven, proofs start from there",
+        our.package(),
+        state.block_number()
+    );
+    // none of this is in the write-ahead log, so it starts over from here
+    wal.snapshot(state)
+}
+
+/// The genesis in genesis.json (which is checked on startup, so this can't fail later on)
+fn genesis() -> Genesis<ChessState> {
+    serde_json::from_str(GENESIS).expect("bad genesis.json")
+}
+
+fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// Whether the ELF was built from this rollup-core, for `genesis`: the elf_program build
+/// writes what it was built for into the program (see rollup_core::built_for)
+fn elf_built_for(genesis: &Genesis<ChessState>) -> bool {
+    let built_for = rollup_core::built_for(genesis.hash());
+    ELF.windows(built_for.len())
+        .any(|bytes| bytes == built_for.as_bytes())
 }
 
 /// Handle HTTP requests from our own frontend.
 fn handle_http_request(
     our: &Address,
     state: &mut FullRollupState,
+    wal: &mut Wal,
+    mempool: &mut Mempool<ChessTransactions>,
     connection: &mut Option<u32>,
     message: &Message,
 ) -> anyhow::Result<()> {
@@ -137,7 +316,7 @@ fn handle_http_request(
         // GETs and POSTs are reads and writes to the chain, respectively
         // essentially, this is our RPC API
         http::HttpServerRequest::Http(ref incoming) => {
-            state.rpc(incoming)?;
+            state.rpc(our, incoming, wal, mempool)?;
             Ok(())
         }
         // this is for connecting to the prover_extension
@@ -153,7 +332,8 @@ fn handle_http_request(
             }
             println!("sequencer: dropped connection with prover_extension");
             *connection = None;
-            Ok(())
+            // a proof that was being made won't come back anymore
+            Archive::open(our)?.cancel_proof()
         }
         // this is for receiving resopnses to when we receive a proof back (see below for the request)
         http::HttpServerRequest::WebSocketPush {
@@ -182,16 +362,23 @@ fn handle_http_request(
             let proof_file = create_file(&format!("{}/proof.json", &drive_path), Some(5))?;
             proof_file.write(&blob)?;
             // TODO post this proof to the L1 verifier
+
+            // the proven blocks don't need to be kept in the state anymore
+            if let Some(through) = Archive::open(our)?.finish_proof(state)? {
+                println!("sequencer: archived blocks up to {through}");
+                wal.log(state, WalEntry::ArchiveBlocks(through))?;
+            }
             Ok(())
         }
     }
 }
 
-// the only admin action we have is to prove the current state, more to come in the future
+// admin actions: proving, posting withdrawals, and maintenance of the saved state
 fn handle_admin_message(
     our: &Address,
     message: &Message,
     state: &mut FullRollupState,
+    wal: &mut Wal,
     connection: &mut Option<u32>,
 ) -> anyhow::Result<()> {
     match serde_json::from_slice::<AdminActions>(message.body())? {
@@ -200,8 +387,21 @@ fn handle_admin_message(
                 return Err(anyhow::anyhow!("no connection"));
             };
 
-            let mut stdin = SP1Stdin::new();
-            stdin.write(&state.sequenced.clone());
+            let archive = Archive::open(our)?;
+            if archive.proving()? {
+                return Err(anyhow::anyhow!("already proving"));
+            }
+            // only sealed blocks are proven, so seal the open block to prove everything so far.
+            // they are replayed on top of the state at the latest archived (i.e. proven) block,
+            // or on top of genesis if nothing has been proven yet
+            seal_block(state)?;
+            wal.sync(state)?;
+            archive.start_proof(state)?;
+            let base = match archive.base()? {
+                Some(base) => base,
+                None => FullRollupState::from_genesis(&genesis()),
+            };
+            let stdin = prover_input(&base, &state.blocks);
 
             // send a request to the prover_extension to prove the current state
             Request::new()
@@ -223,20 +423,26 @@ fn handle_admin_message(
             Ok(())
         }
         AdminActions::BatchWithdrawals => {
-            let batch = WithdrawTree::new(state.withdrawals.clone());
-            state.batches.push(batch);
-
-            // backup the withdrawal tree to vfs
-            let drive_path: String = create_drive(our.package_id(), "batches", Some(5))?;
-            let withdrawal_file = create_file(
-                &format!("{}/{}.json", &drive_path, &state.batches.len()),
-                Some(5),
-            )?;
-            withdrawal_file.write(&serde_json::to_vec(state.batches.last().unwrap()).unwrap())?;
-
-            state.withdrawals = vec![];
-
-            Ok(())
+            // one withdraw tree per asset, each posted to L1 under its own batch index
+            let batches = state.next_batches();
+            let Some((first, _)) = batches.first() else {
+                return Err(anyhow::anyhow!("no withdrawals to batch"));
+            };
+            let seal = seal_transaction(*first);
+            let drive_path: String = create_drive(our.package_id(), BATCHES_DRIVE, Some(5))?;
+            for (index, withdrawals) in &batches {
+                // only the root is kept in state: the rpc generates proofs from this file
+                let withdrawal_file =
+                    create_file(&format!("{}/{}.json", &drive_path, index), Some(5))?;
+                withdrawal_file.write(&serde_json::to_vec(withdrawals)?)?;
+            }
+            // sealed by a transaction like any other, so that the blocks replay to the same batches
+            state.execute(seal)?;
+            wal.sync(state)
+        }
+        AdminActions::SetStateCodec(codec) => {
+            set_codec(codec);
+            state.save()
         }
     }
 }
@@ -0,0 +1,437 @@
+use alloy_primitives::{Address as AlloyAddress, FixedBytes, U256};
+use kinode_process_lib::{http, println};
+use rollup_core::rollup_lib::{BaseRollupState, ExecutionEngine, RollupError, SignedTransaction};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// How far ahead of its sender's nonce a transaction may be queued
+const MAX_NONCE_GAP: u64 = 64;
+/// How many transactions a single account may have queued
+const MAX_QUEUED_PER_ACCOUNT: usize = 16;
+/// How many transactions may be queued across all accounts
+const MAX_QUEUED: usize = 4_096;
+/// How long (in seconds) a queued transaction waits for its nonce gap to fill before it is dropped
+const MAX_QUEUED_AGE: u64 = 10 * 60;
+
+/// The mempool holds transactions that arrived ahead of their sender's nonce (e.g. two moves
+/// signed in quick succession whose requests arrive out of order) until the gap fills.
+/// It only lives in the sequencer's memory: it is not part of the rollup state, and is not
+/// persisted, so queued transactions have to be resubmitted if the sequencer restarts.
+pub struct Mempool<T> {
+    queued: HashMap<AlloyAddress, BTreeMap<U256, Queued<T>>>,
+    len: usize,
+}
+
+struct Queued<T> {
+    stx: SignedTransaction<T>,
+    hash: FixedBytes<32>,
+    received: u64,
+}
+
+/// Why a transaction could not be queued
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum MempoolError {
+    Rejected(RollupError),
+    NonceTooFarAhead {
+        expected: U256,
+        got: U256,
+    },
+    AccountQueueFull(AlloyAddress),
+    QueueFull,
+    /// another transaction is already queued with this nonce, offering at least as much fee
+    NonceQueued(U256),
+}
+
+impl std::fmt::Display for MempoolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MempoolError::Rejected(e) => write!(f, "{e}"),
+            MempoolError::NonceTooFarAhead { expected, got } => write!(
+                f,
+                "nonce {got} is too far ahead of {expected} (at most {MAX_NONCE_GAP} can be queued)"
+            ),
+            MempoolError::AccountQueueFull(account) => write!(
+                f,
+                "{account} already has {MAX_QUEUED_PER_ACCOUNT} transactions queued"
+            ),
+            MempoolError::QueueFull => write!(f, "the mempool is full, try again later"),
+            MempoolError::NonceQueued(nonce) => write!(
+                f,
+                "another transaction with nonce {nonce} is already queued, only one with a higher \
+                 max_fee can replace it"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MempoolError {}
+
+impl MempoolError {
+    pub fn status(&self) -> http::StatusCode {
+        match self {
+            MempoolError::Rejected(RollupError::BadSignature) => http::StatusCode::UNAUTHORIZED,
+            MempoolError::Rejected(_) => http::StatusCode::UNPROCESSABLE_ENTITY,
+            MempoolError::NonceTooFarAhead { .. } | MempoolError::NonceQueued(_) => {
+                http::StatusCode::CONFLICT
+            }
+            MempoolError::AccountQueueFull(_) => http::StatusCode::TOO_MANY_REQUESTS,
+            MempoolError::QueueFull => http::StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+}
+
+impl<T> Default for Mempool<T> {
+    fn default() -> Self {
+        Mempool {
+            queued: HashMap::new(),
+            len: 0,
+        }
+    }
+}
+
+impl<T: Serialize> Mempool<T> {
+    /// Queue a transaction whose nonce is ahead of its sender's `expected` nonce.
+    /// A transaction queued for a nonce keeps it until one that offers a higher fee (see
+    /// offered_fee) replaces it.
+    pub fn queue<S, E>(
+        &mut self,
+        state: &BaseRollupState<S, T, E>,
+        stx: SignedTransaction<T>,
+        expected: U256,
+        now: u64,
+    ) -> Result<FixedBytes<32>, MempoolError> {
+        // don't hold on to anything the sender (or one of their session keys) didn't sign
+        state.authorize(&stx).map_err(MempoolError::Rejected)?;
+        stx.verify_sponsorship().map_err(MempoolError::Rejected)?;
+        let nonce = stx.tx.nonce;
+        if nonce > expected + U256::from(MAX_NONCE_GAP) {
+            return Err(MempoolError::NonceTooFarAhead {
+                expected,
+                got: nonce,
+            });
+        }
+
+        let hash = stx.hash();
+        let queue = self.queued.entry(stx.pub_key).or_default();
+        match queue.get(&nonce) {
+            // resubmitting the same transaction is a no-op
+            Some(old) if old.hash == hash => return Ok(hash),
+            Some(old) if offered_fee(&stx) > offered_fee(&old.stx) => {}
+            Some(_) => return Err(MempoolError::NonceQueued(nonce)),
+            None => {
+                if queue.len() >= MAX_QUEUED_PER_ACCOUNT {
+                    return Err(MempoolError::AccountQueueFull(stx.pub_key));
+                }
+                if self.len >= MAX_QUEUED {
+                    return Err(MempoolError::QueueFull);
+                }
+                self.len += 1;
+            }
+        }
+        queue.insert(
+            nonce,
+            Queued {
+                stx,
+                hash,
+                received: now,
+            },
+        );
+        Ok(hash)
+    }
+
+    /// A queued transaction, by hash
+    pub fn get(&self, hash: &FixedBytes<32>) -> Option<&SignedTransaction<T>> {
+        self.queued
+            .values()
+            .flat_map(|queue| queue.values())
+            .find(|queued| &queued.hash == hash)
+            .map(|queued| &queued.stx)
+    }
+
+    /// Sequence the account's queued transactions that are now next in line, in nonce order.
+    /// A queued transaction that fails is dropped, leaving a gap for its sender to fill again.
+    pub fn promote<S, E>(
+        &mut self,
+        state: &mut BaseRollupState<S, T, E>,
+        account: &AlloyAddress,
+    ) -> Vec<FixedBytes<32>>
+    where
+        BaseRollupState<S, T, E>: ExecutionEngine<T>,
+    {
+        let mut sequenced = vec![];
+        let Some(queue) = self.queued.get_mut(account) else {
+            return sequenced;
+        };
+        while let Some(entry) = queue.first_entry() {
+            let expected = state.nonce_of(account);
+            if *entry.key() > expected {
+                break;
+            }
+            let nonce = *entry.key();
+            let queued = entry.remove();
+            self.len -= 1;
+            // anything below the expected nonce can never be sequenced anymore
+            if nonce < expected {
+                continue;
+            }
+            match state.execute(queued.stx) {
+                Ok(()) => sequenced.push(queued.hash),
+                Err(e) => println!("mempool: dropped queued transaction {}: {e}", queued.hash),
+            }
+        }
+        if queue.is_empty() {
+            self.queued.remove(account);
+        }
+        sequenced
+    }
+
+    /// Drop every transaction that has waited too long, expired, or whose nonce has been used
+    pub fn evict<S, E>(&mut self, state: &BaseRollupState<S, T, E>, now: u64) {
+        for (account, queue) in self.queued.iter_mut() {
+            let expected = state.nonce_of(account);
+            queue.retain(|nonce, queued| {
+                *nonce >= expected
+                    && now.saturating_sub(queued.received) <= MAX_QUEUED_AGE
+                    && state.check_valid_until(&queued.stx.tx).is_ok()
+            });
+        }
+        self.queued.retain(|_, queue| !queue.is_empty());
+        self.len = self.queued.values().map(|queue| queue.len()).sum();
+    }
+}
+
+/// The most a transaction offers to pay: the max_fee of its sponsorship. Without one, the sender
+/// pays whatever the fee comes to, and offers nothing to rank it by.
+fn offered_fee<T>(stx: &SignedTransaction<T>) -> U256 {
+    stx.sponsorship
+        .as_ref()
+        .map(|sponsorship| sponsorship.max_fee)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_primitives::{eip191_hash_message, Bytes, Signature};
+    use k256::ecdsa::SigningKey;
+    use rollup_core::engine::{ChessTransactions, FullRollupState};
+    use rollup_core::rollup_lib::{
+        Sponsorship, Transaction, TransactionData, ValidUntil, GAS_TOKEN,
+    };
+
+    type Stx = SignedTransaction<ChessTransactions>;
+
+    fn key() -> SigningKey {
+        SigningKey::from_slice(&[7; 32]).unwrap()
+    }
+
+    fn account() -> AlloyAddress {
+        AlloyAddress::from_public_key(key().verifying_key())
+    }
+
+    fn personal_sign(key: &SigningKey, message: &str) -> Signature {
+        let (sig, recovery_id) = key
+            .sign_prehash_recoverable(eip191_hash_message(message).as_slice())
+            .unwrap();
+        Signature::from((sig, recovery_id))
+    }
+
+    fn sign(tx: Transaction<ChessTransactions>) -> Stx {
+        SignedTransaction {
+            pub_key: account(),
+            sig: personal_sign(&key(), &serde_json::to_string(&tx).unwrap()),
+            tx,
+            sponsorship: None,
+            cosigs: vec![],
+        }
+    }
+
+    /// `stx`, with its fee paid by another key up to `max_fee`
+    fn sponsor(max_fee: u64, stx: Stx) -> Stx {
+        #[derive(Serialize)]
+        struct SponsorMessage {
+            sig: Bytes,
+            max_fee: U256,
+        }
+        let sponsor = SigningKey::from_slice(&[8; 32]).unwrap();
+        let max_fee = U256::from(max_fee);
+        let message = serde_json::to_string(&SponsorMessage {
+            sig: Bytes::from(stx.sig.as_bytes().to_vec()),
+            max_fee,
+        })
+        .unwrap();
+        SignedTransaction {
+            sponsorship: Some(Sponsorship {
+                sponsor: AlloyAddress::from_public_key(sponsor.verifying_key()),
+                max_fee,
+                sig: personal_sign(&sponsor, &message),
+            }),
+            ..stx
+        }
+    }
+
+    fn withdraw(nonce: u64, amount: u64) -> Stx {
+        sign(Transaction {
+            nonce: U256::from(nonce),
+            data: TransactionData::WithdrawTokens {
+                asset: GAS_TOKEN,
+                amount: U256::from(amount),
+            },
+            valid_until: None,
+        })
+    }
+
+    /// A state in which the account has some gas token to withdraw
+    fn funded() -> FullRollupState {
+        let mut state = FullRollupState::default();
+        state
+            .execute(SignedTransaction {
+                pub_key: account(),
+                sig: Signature::test_signature(),
+                tx: Transaction {
+                    nonce: U256::from(1),
+                    data: TransactionData::BridgeTokens {
+                        asset: GAS_TOKEN,
+                        amount: U256::from(1_000),
+                        block: U256::from(1),
+                    },
+                    valid_until: None,
+                },
+                sponsorship: None,
+                cosigs: vec![],
+            })
+            .unwrap();
+        state
+    }
+
+    #[test]
+    fn promotes_queued_transactions_in_nonce_order() {
+        let mut state = funded();
+        let mut mempool = Mempool::default();
+        let second = mempool
+            .queue(&state, withdraw(2, 1), U256::ZERO, 0)
+            .unwrap();
+        let first = mempool
+            .queue(&state, withdraw(1, 1), U256::ZERO, 0)
+            .unwrap();
+        assert!(mempool.get(&first).is_some());
+
+        // nothing is next in line until nonce 0 is sequenced
+        assert!(mempool.promote(&mut state, &account()).is_empty());
+        assert_eq!(mempool.len, 2);
+
+        state.execute(withdraw(0, 1)).unwrap();
+        assert_eq!(mempool.promote(&mut state, &account()), vec![first, second]);
+        assert_eq!(state.nonce_of(&account()), U256::from(3));
+        assert_eq!(mempool.len, 0);
+        assert!(mempool.get(&first).is_none());
+        assert!(mempool.queued.is_empty());
+    }
+
+    #[test]
+    fn queues_a_nonce_once() {
+        let state = funded();
+        let mut mempool = Mempool::default();
+        let hash = mempool
+            .queue(&state, withdraw(1, 1), U256::ZERO, 0)
+            .unwrap();
+        assert_eq!(
+            mempool.queue(&state, withdraw(1, 1), U256::ZERO, 5),
+            Ok(hash)
+        );
+        assert_eq!(
+            mempool.queue(&state, withdraw(1, 2), U256::ZERO, 5),
+            Err(MempoolError::NonceQueued(U256::from(1)))
+        );
+        assert_eq!(mempool.len, 1);
+
+        let too_far = MAX_NONCE_GAP + 1;
+        assert_eq!(
+            mempool.queue(&state, withdraw(too_far, 1), U256::ZERO, 0),
+            Err(MempoolError::NonceTooFarAhead {
+                expected: U256::ZERO,
+                got: U256::from(too_far),
+            })
+        );
+        for nonce in 2..=MAX_QUEUED_PER_ACCOUNT as u64 {
+            mempool
+                .queue(&state, withdraw(nonce, 1), U256::ZERO, 0)
+                .unwrap();
+        }
+        assert_eq!(
+            mempool.queue(&state, withdraw(MAX_NONCE_GAP, 1), U256::ZERO, 0),
+            Err(MempoolError::AccountQueueFull(account()))
+        );
+
+        // the signature has to match what was signed
+        let mut forged = withdraw(40, 1);
+        forged.tx.nonce = U256::from(41);
+        assert_eq!(
+            mempool.queue(&state, forged, U256::ZERO, 0),
+            Err(MempoolError::Rejected(RollupError::BadSignature))
+        );
+    }
+
+    #[test]
+    fn replaces_a_queued_transaction_with_one_that_offers_a_higher_fee() {
+        let state = funded();
+        let mut mempool = Mempool::default();
+        mempool
+            .queue(&state, withdraw(1, 1), U256::ZERO, 0)
+            .unwrap();
+        let offers_10 = mempool
+            .queue(&state, sponsor(10, withdraw(1, 2)), U256::ZERO, 0)
+            .unwrap();
+        assert_eq!(mempool.len, 1);
+
+        // not for as much, nor for less
+        for max_fee in [10, 9] {
+            assert_eq!(
+                mempool.queue(&state, sponsor(max_fee, withdraw(1, 3)), U256::ZERO, 0),
+                Err(MempoolError::NonceQueued(U256::from(1)))
+            );
+        }
+        assert_eq!(
+            mempool.queue(&state, withdraw(1, 3), U256::ZERO, 0),
+            Err(MempoolError::NonceQueued(U256::from(1)))
+        );
+        assert!(mempool.get(&offers_10).is_some());
+
+        let offers_11 = mempool
+            .queue(&state, sponsor(11, withdraw(1, 3)), U256::ZERO, 0)
+            .unwrap();
+        assert!(mempool.get(&offers_10).is_none());
+        assert!(mempool.get(&offers_11).is_some());
+        assert_eq!(mempool.len, 1);
+    }
+
+    #[test]
+    fn evicts_stale_expired_and_used_nonces() {
+        let mut state = funded();
+        let mut mempool = Mempool::default();
+        mempool
+            .queue(&state, withdraw(1, 1), U256::ZERO, 0)
+            .unwrap();
+        let expiring = sign(Transaction {
+            valid_until: Some(ValidUntil::Timestamp(50)),
+            ..withdraw(2, 1).tx
+        });
+        mempool.queue(&state, expiring, U256::ZERO, 0).unwrap();
+        let late = mempool
+            .queue(&state, withdraw(3, 1), U256::ZERO, 100)
+            .unwrap();
+
+        // nonce 1 is used by another transaction, and nonce 2 expires
+        state.execute(withdraw(0, 1)).unwrap();
+        state.execute(withdraw(1, 2)).unwrap();
+        state.timestamp = 60;
+        mempool.evict(&state, MAX_QUEUED_AGE);
+        assert_eq!(mempool.len, 1);
+        assert!(mempool.get(&late).is_some());
+
+        // the rest waits too long for nonce 2
+        mempool.evict(&state, 100 + MAX_QUEUED_AGE + 1);
+        assert_eq!(mempool.len, 0);
+        assert!(mempool.queued.is_empty());
+    }
+}
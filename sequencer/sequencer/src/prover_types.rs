@@ -1 +0,0 @@
-../../../prover_extension/src/prover_types.rs
\ No newline at end of file
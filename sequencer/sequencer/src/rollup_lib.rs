@@ -1 +0,0 @@
-../../../elf_program/src/rollup_lib.rs
\ No newline at end of file
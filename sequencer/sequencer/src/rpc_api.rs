@@ -0,0 +1,454 @@
+use crate::archive::Archive;
+use crate::mempool::Mempool;
+use crate::wal::Wal;
+use crate::{now, BATCHES_DRIVE};
+use alloy_primitives::{keccak256, Address as AlloyAddress, FixedBytes, U256};
+use kinode_process_lib::{
+    get_blob, http,
+    vfs::{create_drive, open_file},
+    Address,
+};
+use rollup_core::engine::{game_key, ChessEvents, ChessTransactions, FullRollupState, GameId};
+use rollup_core::rollup_lib::{
+    balance_key, nonce_key, Asset, Block, Claim, ExecutionEngine, Receipt, RollupError,
+    SignedTransaction, SparseMerkleTree, TransactionData, WithdrawTree, GAS_TOKEN,
+};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// The RpcApi trait implements all the logic for the RPC API
+/// There is a good reason to separate this from the ExecutionEngine: the EE should be ignorant of
+/// all things related to kinode, http, etc. It should only know how to apply transactions.
+pub trait RpcApi {
+    fn rpc(
+        &mut self,
+        our: &Address,
+        req: &http::IncomingHttpRequest,
+        wal: &mut Wal,
+        mempool: &mut Mempool<ChessTransactions>,
+    ) -> anyhow::Result<()>;
+}
+
+impl RpcApi for FullRollupState {
+    // logic for handling incoming http requests
+    fn rpc(
+        &mut self,
+        our: &Address,
+        req: &http::IncomingHttpRequest,
+        wal: &mut Wal,
+        mempool: &mut Mempool<ChessTransactions>,
+    ) -> anyhow::Result<()> {
+        match req.method()?.as_str() {
+            // chain reads
+            "GET" => {
+                // chain reads are picked with the `method` query parameter
+                // by default, for simplicity, we just return the entire state
+                let params = req.query_params();
+                let response = match params.get("method").map(|method| method.as_str()) {
+                    None | Some("state") => Ok(serde_json::to_value(&self)?),
+                    Some("stateRoot") => state_root_rpc(self, our, params),
+                    Some("getAccountProof") => account_proof_rpc(self, our, params),
+                    Some("getGameProof") => game_proof_rpc(self, our, params),
+                    Some("getTransaction") => transaction_rpc(self, our, mempool, params),
+                    Some("getTransactionReceipt") => receipt_rpc(self, our, params),
+                    Some("getWithdrawals") => withdrawals_rpc(self, params),
+                    Some("getWithdrawalClaims") => withdrawal_claims_rpc(self, our, params),
+                    Some(method) => Err((
+                        http::StatusCode::BAD_REQUEST,
+                        format!("unknown method {method}"),
+                    )),
+                };
+                match response {
+                    Ok(body) => send_json(http::StatusCode::OK, &body),
+                    Err((status, message)) => {
+                        send_json(status, &serde_json::json!({ "message": message }))
+                    }
+                }
+            }
+            // chain writes (handle transactions)
+            "POST" => {
+                // get the blob from the request
+                let Some(blob) = get_blob() else {
+                    return Ok(http::send_response(
+                        http::StatusCode::BAD_REQUEST,
+                        None,
+                        vec![],
+                    ));
+                };
+                // deserialize the blob into a SignedTransaction
+                let Ok(tx) =
+                    serde_json::from_slice::<SignedTransaction<ChessTransactions>>(&blob.bytes)
+                else {
+                    return Ok(http::send_response(
+                        http::StatusCode::BAD_REQUEST,
+                        None,
+                        vec![],
+                    ));
+                };
+
+                // execute the transaction, and report any errors like a bad signature or bad move
+                // NOTE: deposits only come from the bridge (see bridge_lib::handle_log), and seals
+                // from the sequencer itself, never http
+                let tx_hash = tx.hash();
+                let sender = tx.pub_key;
+                let result = match tx.tx.data {
+                    TransactionData::BridgeTokens { .. } | TransactionData::SealWithdrawals => {
+                        Err(RollupError::Unauthorized)
+                    }
+                    _ => self.execute(tx.clone()),
+                };
+                match result {
+                    Ok(()) => {}
+                    // a transaction from the future waits in the mempool until the gap fills
+                    Err(RollupError::BadNonce { expected, got }) if got > expected => {
+                        return match mempool.queue(self, tx, expected, now()) {
+                            Ok(tx_hash) => send_json(
+                                http::StatusCode::ACCEPTED,
+                                &serde_json::json!({
+                                    "tx_hash": tx_hash,
+                                    "queued": true,
+                                    "expected_nonce": expected,
+                                }),
+                            ),
+                            Err(e) => send_json(
+                                e.status(),
+                                &serde_json::json!({
+                                    "error": e,
+                                    "message": e.to_string(),
+                                }),
+                            ),
+                        };
+                    }
+                    Err(e) => {
+                        return send_json(
+                            error_status(&e),
+                            &serde_json::json!({
+                                "error": e,
+                                "message": e.to_string(),
+                            }),
+                        );
+                    }
+                }
+                // the sender's queued transactions may be next in line now
+                mempool.promote(self, &sender);
+                // it is only sequenced once it is in the write-ahead log
+                wal.sync(self)?;
+                // send the receipt to the frontend to confirm that the transaction was sequenced
+                send_json(http::StatusCode::OK, &self.receipts[&tx_hash])
+            }
+            // Any other http method will be rejected
+            // feel free to add more methods if you need them
+            _ => Ok(http::send_response(
+                http::StatusCode::METHOD_NOT_ALLOWED,
+                None,
+                vec![],
+            )),
+        }
+    }
+}
+
+/// Read-only RPC methods either return a JSON body, or a status and a message explaining why not
+type RpcResult = Result<serde_json::Value, (http::StatusCode, String)>;
+
+fn state_root_rpc(
+    state: &FullRollupState,
+    our: &Address,
+    params: &HashMap<String, String>,
+) -> RpcResult {
+    let block = block_param(state, our, params)?;
+    Ok(serde_json::json!({
+        "block": block.header.number,
+        "block_hash": block.header.hash(),
+        "state_root": block.header.state_root,
+    }))
+}
+
+/// An account's balance (of `asset`, the gas token by default) and nonce, with proofs
+/// against the state root of `block`
+fn account_proof_rpc(
+    state: &FullRollupState,
+    our: &Address,
+    params: &HashMap<String, String>,
+) -> RpcResult {
+    let Some(address) = parse_param::<AlloyAddress>(params, "address")? else {
+        return Err((http::StatusCode::BAD_REQUEST, "missing address".to_string()));
+    };
+    let asset = parse_param::<Asset>(params, "asset")?.unwrap_or(GAS_TOKEN);
+    let (block, tree) = state_tree_param(state, our, params)?;
+
+    let balance_key = balance_key(&asset, &address);
+    let nonce_key = nonce_key(&address);
+    let balance = tree.get(&balance_key).copied().unwrap_or_default();
+    let nonce = tree.get(&nonce_key).copied().unwrap_or_default();
+    Ok(serde_json::json!({
+        "block": block.header.number,
+        "state_root": block.header.state_root,
+        "address": address,
+        "asset": asset,
+        "balance": U256::from_be_bytes(balance.0),
+        "balance_proof": tree.prove(&balance_key),
+        "nonce": U256::from_be_bytes(nonce.0),
+        "nonce_proof": tree.prove(&nonce_key),
+    }))
+}
+
+/// A game, with a proof against the state root of `block`. The tree only holds the hash of
+/// each game, so this only works if the game hasn't changed since that block.
+fn game_proof_rpc(
+    state: &FullRollupState,
+    our: &Address,
+    params: &HashMap<String, String>,
+) -> RpcResult {
+    let Some(game_id) = parse_param::<GameId>(params, "game_id")? else {
+        return Err((http::StatusCode::BAD_REQUEST, "missing game_id".to_string()));
+    };
+    let Some(game) = state.state.games.get(&game_id) else {
+        return Err((http::StatusCode::NOT_FOUND, "no such game".to_string()));
+    };
+    let (block, tree) = state_tree_param(state, our, params)?;
+
+    let key = game_key(&game_id);
+    if tree.get(&key) != Some(&keccak256(serde_json::to_vec(game).unwrap())) {
+        return Err((
+            http::StatusCode::CONFLICT,
+            "game has changed since this block, try the next one".to_string(),
+        ));
+    }
+    Ok(serde_json::json!({
+        "block": block.header.number,
+        "state_root": block.header.state_root,
+        "game_id": game_id,
+        "game": game,
+        "proof": tree.prove(&key),
+    }))
+}
+
+/// A sequenced transaction by hash. `sealed` tells whether its block has been sealed yet, and
+/// `queued` whether it is still waiting in the mempool for an earlier nonce.
+fn transaction_rpc(
+    state: &FullRollupState,
+    our: &Address,
+    mempool: &Mempool<ChessTransactions>,
+    params: &HashMap<String, String>,
+) -> RpcResult {
+    let queued = parse_param::<FixedBytes<32>>(params, "hash")?.and_then(|hash| mempool.get(&hash));
+    if let Some(transaction) = queued {
+        return Ok(serde_json::json!({
+            "transaction": transaction,
+            "queued": true,
+            "sealed": false,
+        }));
+    }
+    let (transaction, receipt) = transaction_param(state, our, params)?;
+    Ok(serde_json::json!({
+        "transaction": transaction,
+        "block_number": receipt.block_number,
+        "index": receipt.index,
+        "queued": false,
+        "sealed": receipt.block_number < state.block_number(),
+    }))
+}
+
+fn receipt_rpc(
+    state: &FullRollupState,
+    our: &Address,
+    params: &HashMap<String, String>,
+) -> RpcResult {
+    let (_, receipt) = transaction_param(state, our, params)?;
+    Ok(serde_json::to_value(receipt).unwrap())
+}
+
+/// Every withdrawal `address` has made, and how far along each one is (see WithdrawalStatus)
+fn withdrawals_rpc(state: &FullRollupState, params: &HashMap<String, String>) -> RpcResult {
+    let Some(address) = parse_param::<AlloyAddress>(params, "address")? else {
+        return Err((http::StatusCode::BAD_REQUEST, "missing address".to_string()));
+    };
+    let records = state
+        .withdrawal_records
+        .get(&address)
+        .map(|records| records.as_slice())
+        .unwrap_or_default();
+    Ok(serde_json::json!({ "address": address, "withdrawals": records }))
+}
+
+/// Everything `address` can withdraw on L1: a claim (with its proof) for each of its
+/// withdrawals, in every batch or only in `batch`, of every asset or only of `asset`. Claims on
+/// batches that aren't `verified` yet can't be withdrawn until the batch root has been posted.
+fn withdrawal_claims_rpc(
+    state: &FullRollupState,
+    our: &Address,
+    params: &HashMap<String, String>,
+) -> RpcResult {
+    let Some(address) = parse_param::<AlloyAddress>(params, "address")? else {
+        return Err((http::StatusCode::BAD_REQUEST, "missing address".to_string()));
+    };
+    let batches = match parse_param::<usize>(params, "batch")? {
+        None => 0..state.batches.len(),
+        Some(index) if index < state.batches.len() => index..index + 1,
+        Some(_) => return Err((http::StatusCode::NOT_FOUND, "no such batch".to_string())),
+    };
+    let asset = parse_param::<AlloyAddress>(params, "asset")?;
+    let drive_path =
+        create_drive(our.package_id(), BATCHES_DRIVE, Some(5)).map_err(internal_error)?;
+
+    let mut claims = vec![];
+    let mut push_claim = |index: usize, batch: &WithdrawTree, claim: &Claim| {
+        claims.push(serde_json::json!({
+            "batch": index,
+            "asset": batch.asset,
+            "root": batch.root,
+            "verified": batch.verified,
+            "index": claim.index,
+            "amount": claim.amount,
+            "proof": claim.proof,
+        }))
+    };
+    for index in batches {
+        let batch = &state.batches[index];
+        if asset.is_some_and(|asset| asset != batch.asset) {
+            continue;
+        }
+        // batches from before the withdrawals were written out keep their claims in state (for
+        // leaves without an asset, as the bridge they were posted to had them)
+        if let Some(legacy) = &batch.claims {
+            if let Some(claim) = legacy.get(&address) {
+                push_claim(index, batch, claim);
+            }
+            continue;
+        }
+        let file = open_file(&format!("{}/{}.json", drive_path, index), false, Some(5))
+            .map_err(internal_error)?;
+        let withdrawals: Vec<(AlloyAddress, U256)> =
+            serde_json::from_slice(&file.read().map_err(internal_error)?)
+                .map_err(|e| internal_error(e.into()))?;
+        for (i, (account, _)) in withdrawals.iter().enumerate() {
+            if *account != address {
+                continue;
+            }
+            let claim = WithdrawTree::claim(batch.asset, &withdrawals, i).unwrap();
+            push_claim(index, batch, &claim);
+        }
+    }
+    Ok(serde_json::json!({ "address": address, "claims": claims }))
+}
+
+/// The transaction picked by the `hash` parameter, with its receipt. Transactions in archived
+/// blocks are read from the archive.
+fn transaction_param(
+    state: &FullRollupState,
+    our: &Address,
+    params: &HashMap<String, String>,
+) -> Result<(SignedTransaction<ChessTransactions>, Receipt<ChessEvents>), (http::StatusCode, String)>
+{
+    let Some(hash) = parse_param::<FixedBytes<32>>(params, "hash")? else {
+        return Err((http::StatusCode::BAD_REQUEST, "missing hash".to_string()));
+    };
+    let found = match state.transaction(&hash) {
+        Some((transaction, receipt)) => Some((transaction.clone(), receipt.clone())),
+        None => Archive::open(our)
+            .and_then(|archive| archive.transaction(&hash))
+            .map_err(internal_error)?,
+    };
+    found.ok_or((
+        http::StatusCode::NOT_FOUND,
+        "no such transaction".to_string(),
+    ))
+}
+
+/// The sealed block picked by the `block` parameter (by default the latest one). Archived
+/// blocks are read from the archive.
+fn block_param(
+    state: &FullRollupState,
+    our: &Address,
+    params: &HashMap<String, String>,
+) -> Result<Block<ChessTransactions>, (http::StatusCode, String)> {
+    let number = match parse_param::<u64>(params, "block")? {
+        Some(number) => number,
+        None => match state.latest_header() {
+            Some(header) => header.number,
+            None => return Err((http::StatusCode::NOT_FOUND, "no such block".to_string())),
+        },
+    };
+    let block = match state.blocks.iter().find(|b| b.header.number == number) {
+        Some(block) => Some(block.clone()),
+        None => Archive::open(our)
+            .and_then(|archive| archive.block(number))
+            .map_err(internal_error)?,
+    };
+    block.ok_or((http::StatusCode::NOT_FOUND, "no such block".to_string()))
+}
+
+fn state_tree_param(
+    state: &FullRollupState,
+    our: &Address,
+    params: &HashMap<String, String>,
+) -> Result<(Block<ChessTransactions>, SparseMerkleTree), (http::StatusCode, String)> {
+    let block = block_param(state, our, params)?;
+    let Some(tree) = state.state_tree_at(block.header.number) else {
+        return Err((
+            http::StatusCode::GONE,
+            "state proofs are only kept for recent blocks".to_string(),
+        ));
+    };
+    Ok((block, tree))
+}
+
+fn internal_error(e: anyhow::Error) -> (http::StatusCode, String) {
+    (http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+fn parse_param<P: FromStr>(
+    params: &HashMap<String, String>,
+    name: &str,
+) -> Result<Option<P>, (http::StatusCode, String)> {
+    match params.get(name) {
+        None => Ok(None),
+        Some(value) => match value.parse::<P>() {
+            Ok(value) => Ok(Some(value)),
+            Err(_) => Err((http::StatusCode::BAD_REQUEST, format!("bad {name}"))),
+        },
+    }
+}
+
+fn send_json<B: Serialize>(status: http::StatusCode, body: &B) -> anyhow::Result<()> {
+    http::send_response(
+        status,
+        Some(HashMap::from([(
+            String::from("Content-Type"),
+            String::from("application/json"),
+        )])),
+        serde_json::to_vec(body)?,
+    );
+    Ok(())
+}
+
+/// Map each kind of rejected transaction to the HTTP status the RPC responds with
+fn error_status(error: &RollupError) -> http::StatusCode {
+    match error {
+        RollupError::BadSignature | RollupError::NotEnoughSignatures { .. } => {
+            http::StatusCode::UNAUTHORIZED
+        }
+        RollupError::Unauthorized => http::StatusCode::FORBIDDEN,
+        RollupError::BadNonce { .. } => http::StatusCode::CONFLICT,
+        RollupError::UnknownGame(_) => http::StatusCode::NOT_FOUND,
+        RollupError::Expired(_) => http::StatusCode::GONE,
+        RollupError::FeeTooHigh { .. } => http::StatusCode::PAYMENT_REQUIRED,
+        RollupError::SpendingLimitExceeded { .. } => http::StatusCode::FORBIDDEN,
+        RollupError::RecoveryNotReady(_) => http::StatusCode::CONFLICT,
+        RollupError::BatchFailed { error, .. } => error_status(error),
+        RollupError::NotAPlayer | RollupError::NotYourTurn | RollupError::OutOfScope => {
+            http::StatusCode::FORBIDDEN
+        }
+        RollupError::InsufficientFunds { .. }
+        | RollupError::InsufficientAllowance { .. }
+        | RollupError::BalanceOverflow(_)
+        | RollupError::WithdrawalOverflow(_)
+        | RollupError::GameNotActive(_)
+        | RollupError::InvalidMove(_)
+        | RollupError::InvalidBlock(_)
+        | RollupError::InvalidPolicy(_)
+        | RollupError::NoRecovery
+        | RollupError::NoWithdrawals => http::StatusCode::UNPROCESSABLE_ENTITY,
+    }
+}
@@ -0,0 +1,251 @@
+use alloy_primitives::{Address as AlloyAddress, FixedBytes};
+use kinode_process_lib::{
+    println,
+    vfs::{create_drive, open_dir, open_file, remove_file, File},
+    Address,
+};
+use rollup_core::engine::{ChessTransactions, FullRollupState};
+use rollup_core::persistence::Persistence;
+use rollup_core::rollup_lib::{BlockHeader, ExecutionEngine, SignedTransaction};
+use serde::{Deserialize, Serialize};
+
+/// The vfs drive holding the write-ahead log and the snapshots it starts from
+const WAL_DRIVE: &str = "wal";
+/// How many blocks to seal between two snapshots (each of which starts a new log)
+const SNAPSHOT_INTERVAL: u64 = 100;
+
+/// Everything that changes the state, in the order it happened: replaying the entries on top of
+/// the state they started from gives the same state again
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum WalEntry {
+    /// a transaction that was sequenced into the open block
+    Transaction(SignedTransaction<ChessTransactions>),
+    /// the open block was sealed (with this header)
+    Seal(BlockHeader),
+    /// the open block starts at a new time (since it had no transactions yet). Logged right
+    /// before its first transaction: until then the time changes with every idle block timer,
+    /// and the sequencer restarts an empty open block at the current time anyway.
+    Timestamp(u64),
+    /// a batch root was posted to L1
+    VerifyBatch { batch: usize, root: FixedBytes<32> },
+    /// a withdrawal was claimed on L1
+    ClaimWithdrawal {
+        batch: usize,
+        index: usize,
+        account: AlloyAddress,
+    },
+    /// the sealed blocks up to and including this one were proven, and archived
+    ArchiveBlocks(u64),
+}
+
+/// The write-ahead log keeps every change to the state in the vfs, so that the state can be
+/// rebuilt on startup even if the last `set_state` didn't make it. It is kept in generations:
+/// `snapshot-{n}` is the state when generation n started, and `log-{n}.jsonl` every entry since,
+/// one JSON line each. Every SNAPSHOT_INTERVAL blocks (and on startup), a new generation
+/// replaces the old one, which is only removed once the new snapshot has been written.
+/// The state is only saved (with set_state) along with each snapshot: in between, the log is what
+/// keeps the changes, so every change has to be logged before it is acknowledged.
+pub struct Wal {
+    drive: String,
+    generation: u64,
+    /// the first block of this generation
+    first_block: u64,
+    log: File,
+    /// how far the log has come: the open block, how many of its transactions are logged, and
+    /// the time it started at
+    block: u64,
+    sequenced: usize,
+    timestamp: u64,
+}
+
+impl Wal {
+    /// Open the log, and rebuild the state it holds. Returns that state if it is further along
+    /// than `state` (as loaded with Persistence::load), i.e. if the last saves didn't make it.
+    /// Otherwise `state` is kept, so that edits made to the saved state by hand aren't lost.
+    pub fn open(our: &Address, state: FullRollupState) -> anyhow::Result<(Self, FullRollupState)> {
+        let drive = create_drive(our.package_id(), WAL_DRIVE, Some(5))?;
+        let Some((generation, recovered)) = Self::recover(&drive)? else {
+            // first start with a log: the current state is the first snapshot
+            let wal = Self::start(drive, 0, &state)?;
+            return Ok((wal, state));
+        };
+        let state = if progress(&recovered) > progress(&state) {
+            recovered
+        } else {
+            state
+        };
+        let wal = Self::start(drive, generation + 1, &state)?;
+        wal.remove(generation)?;
+        Ok((wal, state))
+    }
+
+    /// Log whatever transactions and seals have happened since the last sync, and the time of
+    /// each block they went into
+    pub fn sync(&mut self, state: &FullRollupState) -> anyhow::Result<()> {
+        let mut entries = vec![];
+        let logged = self.block;
+        let sealed = state
+            .blocks
+            .iter()
+            .skip_while(|block| block.header.number < logged);
+        for block in sealed {
+            if block.header.timestamp != self.timestamp {
+                entries.push(WalEntry::Timestamp(block.header.timestamp));
+            }
+            entries.extend(
+                block.transactions[self.sequenced..]
+                    .iter()
+                    .cloned()
+                    .map(WalEntry::Transaction),
+            );
+            entries.push(WalEntry::Seal(block.header.clone()));
+            self.block = block.header.number + 1;
+            self.sequenced = 0;
+            self.timestamp = block.header.timestamp;
+        }
+        let sequenced = &state.sequenced[self.sequenced..];
+        if !sequenced.is_empty() && state.timestamp != self.timestamp {
+            entries.push(WalEntry::Timestamp(state.timestamp));
+            self.timestamp = state.timestamp;
+        }
+        entries.extend(sequenced.iter().cloned().map(WalEntry::Transaction));
+        self.sequenced = state.sequenced.len();
+        self.append(&entries)?;
+
+        // start a new generation every so often, so that the log stays quick to replay
+        if self.block - self.first_block >= SNAPSHOT_INTERVAL {
+            self.snapshot(state)?;
+        }
+        Ok(())
+    }
+
+    /// Start a new generation from `state`, e.g. after a change to it that no entry describes
+    pub fn snapshot(&mut self, state: &FullRollupState) -> anyhow::Result<()> {
+        let old = self.generation;
+        *self = Self::start(self.drive.clone(), old + 1, state)?;
+        self.remove(old)
+    }
+
+    /// Log a change to the state that doesn't come from a transaction, right after making it.
+    /// Everything that happened before it has to be logged first, so this syncs first.
+    pub fn log(&mut self, state: &FullRollupState, entry: WalEntry) -> anyhow::Result<()> {
+        self.sync(state)?;
+        self.append(&[entry])
+    }
+
+    fn append(&mut self, entries: &[WalEntry]) -> anyhow::Result<()> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+        let mut lines = vec![];
+        for entry in entries {
+            serde_json::to_writer(&mut lines, entry)?;
+            lines.push(b'\n');
+        }
+        self.log.append(&lines)
+    }
+
+    /// Start a new generation: snapshot the state, and log everything from there on
+    fn start(drive: String, generation: u64, state: &FullRollupState) -> anyhow::Result<Self> {
+        let snapshot = open_file(&snapshot_path(&drive, generation), true, Some(5))?;
+        snapshot.write(&state.encode()?)?;
+        state.save()?;
+        let log = open_file(&log_path(&drive, generation), true, Some(5))?;
+        // in case a previous attempt at this generation left entries behind
+        log.write(&[])?;
+        Ok(Self {
+            drive,
+            generation,
+            first_block: state.block_number(),
+            log,
+            block: state.block_number(),
+            sequenced: state.sequenced.len(),
+            timestamp: state.timestamp,
+        })
+    }
+
+    fn remove(&self, generation: u64) -> anyhow::Result<()> {
+        remove_file(&snapshot_path(&self.drive, generation), Some(5))?;
+        remove_file(&log_path(&self.drive, generation), Some(5))
+    }
+
+    /// The latest generation with a snapshot that can be read, and its state after replaying
+    /// its log. If the log ends in an entry that can't be read or replayed (e.g. a crash while
+    /// writing it), the state is rebuilt up to that entry.
+    fn recover(drive: &str) -> anyhow::Result<Option<(u64, FullRollupState)>> {
+        let mut generations: Vec<u64> = open_dir(drive, false, Some(5))?
+            .read()?
+            .iter()
+            .filter_map(|entry| {
+                let name = entry.path.rsplit('/').next()?;
+                name.strip_prefix("snapshot-")?.parse().ok()
+            })
+            .collect();
+        generations.sort();
+        for generation in generations.into_iter().rev() {
+            let snapshot = open_file(&snapshot_path(drive, generation), false, Some(5))?;
+            let mut state = match FullRollupState::decode(&snapshot.read()?) {
+                Ok(state) => state,
+                Err(e) => {
+                    println!("wal: skipping unreadable snapshot {generation}: {e}");
+                    continue;
+                }
+            };
+            let log = open_file(&log_path(drive, generation), true, Some(5))?;
+            for (line, entry) in log.read()?.split(|b| *b == b'\n').enumerate() {
+                if entry.is_empty() {
+                    continue;
+                }
+                let replayed = serde_json::from_slice::<WalEntry>(entry)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|entry| replay(&mut state, entry));
+                if let Err(e) = replayed {
+                    println!("wal: stopped replaying log {generation} at line {line}: {e}");
+                    break;
+                }
+            }
+            return Ok(Some((generation, state)));
+        }
+        Ok(None)
+    }
+}
+
+fn snapshot_path(drive: &str, generation: u64) -> String {
+    format!("{drive}/snapshot-{generation}")
+}
+
+fn log_path(drive: &str, generation: u64) -> String {
+    format!("{drive}/log-{generation}.jsonl")
+}
+
+fn replay(state: &mut FullRollupState, entry: WalEntry) -> anyhow::Result<()> {
+    match entry {
+        WalEntry::Transaction(stx) => state.execute(stx)?,
+        WalEntry::Seal(header) => {
+            let timestamp = state.timestamp;
+            if state.seal_block(timestamp).header != header {
+                return Err(anyhow::anyhow!("block {} doesn't match", header.number));
+            }
+        }
+        WalEntry::Timestamp(timestamp) => state.timestamp = timestamp,
+        WalEntry::VerifyBatch { batch, root } => {
+            state.verify_batch(batch, root);
+        }
+        WalEntry::ClaimWithdrawal {
+            batch,
+            index,
+            account,
+        } => {
+            state.claim_withdrawal(batch, index, &account);
+        }
+        WalEntry::ArchiveBlocks(through) => {
+            state.archive_blocks(through);
+        }
+    }
+    Ok(())
+}
+
+/// How far along a state is: its open block, and how many transactions are in it
+fn progress(state: &FullRollupState) -> (u64, usize) {
+    (state.block_number(), state.sequenced.len())
+}
@@ -46,7 +46,7 @@ function App() {
             <InitiateWithdraw baseUrl={BASE_URL} />
           </div>
           <div className="py-4 px-2">
-            <Withdraw />
+            <Withdraw baseUrl={BASE_URL} />
           </div>
           <div className="py-4 px-2">
             <Transfer baseUrl={BASE_URL} />
@@ -3,6 +3,8 @@ import { ethers } from "ethers";
 import { useWeb3React } from "@web3-react/core";
 import { BigNumber } from 'ethers'
 import useSequencerStore, { Transaction, SignedTransaction } from "../store";
+import { submitTx } from "../tx";
+import { GAS_TOKEN } from "../libs/constants";
 
 interface WithdrawProps {
     baseUrl: string;
@@ -23,7 +25,10 @@ const InitiateWithdraw = ({ baseUrl }: WithdrawProps) => {
                 }
                 let tx: Transaction = {
                     data: {
-                        WithdrawTokens: BigNumber.from(amount).toHexString().replace(/^0x0+/, '0x'), // for some reason there's a leading zero...really annoying!
+                        WithdrawTokens: {
+                            asset: GAS_TOKEN,
+                            amount: BigNumber.from(amount).toHexString().replace(/^0x0+/, '0x'), // for some reason there's a leading zero...really annoying!
+                        },
                     },
                     nonce: nonces[account.toLowerCase()] ?
                         BigNumber.from(nonces[account.toLowerCase()]++).toHexString().replace(/^0x0+/, '0x') :
@@ -41,13 +46,7 @@ const InitiateWithdraw = ({ baseUrl }: WithdrawProps) => {
                     tx
                 };
 
-                const receipt = await fetch(`${baseUrl}/rpc`, {
-                    method: "POST",
-                    headers: {
-                        "Content-Type": "application/json",
-                    },
-                    body: JSON.stringify(wtx),
-                });
+                const receipt = await submitTx(`${baseUrl}/rpc`, wtx);
                 console.log('receipt', receipt);
             } catch (err) {
                 console.error(err);
@@ -1,12 +1,17 @@
-import { useCallback } from "react";
+import { useCallback, useState } from "react";
 import { ethers } from "ethers";
 import { useWeb3React } from "@web3-react/core";
 import { BigNumber } from 'ethers'
 import useSequencerStore, { Transaction, SignedTransaction } from "../store";
+import { submitTx } from "../tx";
+import { getSessionKey, newSessionKey, saveSessionKey, signTx, SESSION_KEY_DURATION_SECS } from "../session";
 import { Chessboard } from "react-chessboard";
 import { Chess } from "chess.js";
 import Resign from "./Resign";
 
+// how long a signed move may wait to be sequenced before the sequencer refuses it
+const MOVE_VALIDITY_SECS = 60;
+
 interface MyGamesProps {
     baseUrl: string;
 }
@@ -14,6 +19,7 @@ interface MyGamesProps {
 const MyGames = ({ baseUrl }: MyGamesProps) => {
     let { account, provider } = useWeb3React();
     const { nonces, state, set } = useSequencerStore();
+    const [quickMoves, setQuickMoves] = useState(account ? getSessionKey(account) != null : false);
 
     const onDrop = useCallback(
         (sourceSquare: string, targetSquare: string, gameId: string) => {
@@ -56,9 +62,11 @@ const MyGames = ({ baseUrl }: MyGamesProps) => {
                     nonce: nonces[account.toLowerCase()] ?
                         BigNumber.from(nonces[account.toLowerCase()]++).toHexString().replace(/^0x0+/, '0x') :
                         "0x0",
+                    // a move only makes sense for the position it was made in
+                    valid_until: { Timestamp: Math.floor(Date.now() / 1000) + MOVE_VALIDITY_SECS },
                 }
 
-                provider.getSigner().signMessage(JSON.stringify(tx)).then((signature) => {
+                signTx(tx, account, provider).then((signature) => {
                     const { v, r, s } = ethers.utils.splitSignature(signature);
 
                     let wtx: SignedTransaction = {
@@ -69,13 +77,7 @@ const MyGames = ({ baseUrl }: MyGamesProps) => {
                         tx
                     };
 
-                    fetch(`${baseUrl}/rpc`, {
-                        method: "POST",
-                        headers: {
-                            "Content-Type": "application/json",
-                        },
-                        body: JSON.stringify(wtx),
-                    }).then((receipt) => {
+                    submitTx(`${baseUrl}/rpc`, wtx).then((receipt) => {
                         console.log('receipt', receipt);
                         return true;
                     }).catch(err => {
@@ -95,11 +97,54 @@ const MyGames = ({ baseUrl }: MyGamesProps) => {
         [account, provider, state, set]
     );
 
+    // register a session key that can only move and resign, so moves don't need a wallet popup
+    const enableQuickMoves = useCallback(
+        async () => {
+            if (!account || !provider) {
+                window.alert('Ethereum wallet is not connected');
+                return;
+            }
+            try {
+                const sessionKey = newSessionKey();
+                const expiry = Math.floor(Date.now() / 1000) + SESSION_KEY_DURATION_SECS;
+                let tx: Transaction = {
+                    data: {
+                        RegisterSessionKey: {
+                            key: sessionKey.address.toLowerCase(),
+                            expiry: { Timestamp: expiry },
+                            scope: {
+                                actions: ["Move", "Resign"],
+                                target: null,
+                                max_value: null,
+                            },
+                        },
+                    },
+                    nonce: nonces[account.toLowerCase()] ?
+                        BigNumber.from(nonces[account.toLowerCase()]++).toHexString().replace(/^0x0+/, '0x') :
+                        "0x0",
+                }
+
+                const signature = await provider.getSigner().signMessage(JSON.stringify(tx));
+                const { v, r, s } = ethers.utils.splitSignature(signature);
+                const receipt = await submitTx(`${baseUrl}/rpc`, { pub_key: account, sig: { r, s, v }, tx });
+                console.log('receipt', receipt);
+                saveSessionKey(account, sessionKey, expiry);
+                setQuickMoves(true);
+            } catch (err) {
+                console.error(err);
+            }
+        },
+        [account, provider, nonces, setQuickMoves]
+    );
+
     return (
         <div
             className="flex flex-col items-center"
         >
             <h4 className="m-2">Active Games</h4>
+            {account && !quickMoves &&
+                <button onClick={enableQuickMoves}>Enable quick moves</button>
+            }
             <div className="flex flex-col overflow-auto">
                 {Object.keys(state.games).map((gameId, i) => {
                     const { status, turns, board, white, black, wager } = state.games[gameId]; // accepted
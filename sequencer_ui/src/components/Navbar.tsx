@@ -4,10 +4,12 @@ import { ConnectionOptions } from '../libs/components/ConnectionOptions'
 import { ConnectionType } from '../libs/connections'
 import { BigNumber } from 'ethers'
 import useSequencerStore from "../store";
+import { GAS_TOKEN } from "../libs/constants";
 
 const NavBar = () => {
     let { account, isActive } = useWeb3React();
     let { balances } = useSequencerStore();
+    const balance = account && balances[GAS_TOKEN]?.[account.toLowerCase()];
     const [connectionType, setConnectionType] = useState<ConnectionType | null>(null)
 
     return (
@@ -28,7 +30,7 @@ const NavBar = () => {
                 {
                     account && (
                         <div className="py-5 px-3">
-                            <code>{balances[account.toLowerCase()] && `${BigNumber.from(balances[account.toLowerCase()])} WEI`}</code>
+                            <code>{balance && `${BigNumber.from(balance)} WEI`}</code>
                         </div>
                     )
                 }
@@ -3,6 +3,7 @@ import { ethers } from "ethers";
 import { useWeb3React } from "@web3-react/core";
 import { BigNumber } from 'ethers'
 import useSequencerStore, { Transaction, SignedTransaction } from "../store";
+import { submitTx } from "../tx";
 
 interface MyGamesProps {
     baseUrl: string;
@@ -42,13 +43,7 @@ const MyGames = ({ baseUrl }: MyGamesProps) => {
                     tx
                 };
 
-                const receipt = await fetch(`${baseUrl}/rpc`, {
-                    method: "POST",
-                    headers: {
-                        "Content-Type": "application/json",
-                    },
-                    body: JSON.stringify(wtx),
-                });
+                const receipt = await submitTx(`${baseUrl}/rpc`, wtx);
                 console.log('receipt', receipt);
             } catch (err) {
                 console.error(err);
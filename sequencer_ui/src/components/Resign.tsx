@@ -2,6 +2,8 @@ import { useCallback } from "react";
 import { ethers, BigNumber } from "ethers";
 import { useWeb3React } from "@web3-react/core";
 import useSequencerStore, { Transaction, SignedTransaction } from "../store";
+import { submitTx } from "../tx";
+import { signTx } from "../session";
 
 interface ResignProps {
     baseUrl: string;
@@ -30,7 +32,7 @@ const Resign = ({ baseUrl, gameId }: ResignProps) => {
                         "0x0",
                 }
 
-                const signature = await provider.getSigner().signMessage(JSON.stringify(tx));
+                const signature = await signTx(tx, account, provider);
                 const { v, r, s } = ethers.utils.splitSignature(signature);
 
                 let wtx: SignedTransaction = {
@@ -41,13 +43,7 @@ const Resign = ({ baseUrl, gameId }: ResignProps) => {
                     tx
                 };
 
-                const receipt = await fetch(`${baseUrl}/rpc`, {
-                    method: "POST",
-                    headers: {
-                        "Content-Type": "application/json",
-                    },
-                    body: JSON.stringify(wtx),
-                });
+                const receipt = await submitTx(`${baseUrl}/rpc`, wtx);
                 console.log('receipt', receipt);
             } catch (err) {
                 console.error(err);
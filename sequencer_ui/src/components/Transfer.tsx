@@ -3,6 +3,8 @@ import { ethers } from "ethers";
 import { useWeb3React } from "@web3-react/core";
 import { BigNumber } from 'ethers'
 import useSequencerStore, { Transaction, SignedTransaction } from "../store";
+import { submitTx } from "../tx";
+import { GAS_TOKEN } from "../libs/constants";
 
 interface TransferProps {
     baseUrl: string;
@@ -28,6 +30,7 @@ const Transfer = ({ baseUrl }: TransferProps) => {
                 let tx: Transaction = {
                     data: {
                         Transfer: {
+                            asset: GAS_TOKEN,
                             from: account.toLowerCase(),
                             to: transferTo.toLowerCase(),
                             amount: BigNumber.from(transferAmount).toHexString().replace(/^0x0+/, '0x'), // for some reason there's a leading zero...really annoying!
@@ -49,13 +52,7 @@ const Transfer = ({ baseUrl }: TransferProps) => {
                     tx
                 };
 
-                const receipt = await fetch(`${baseUrl}/rpc`, {
-                    method: "POST",
-                    headers: {
-                        "Content-Type": "application/json",
-                    },
-                    body: JSON.stringify(wtx),
-                });
+                const receipt = await submitTx(`${baseUrl}/rpc`, wtx);
                 console.log('receipt', receipt);
             } catch (err) {
                 console.error(err);
@@ -1,16 +1,31 @@
-import { useCallback } from "react";
+import { useCallback, useEffect, useState } from "react";
 import { ethers } from "ethers";
 import { useWeb3React } from "@web3-react/core";
 import ROLLUP_ABI from "../abis/Bridge.json";
-import { BRIDGE_ADDRESS } from "../libs/constants";
+import { BRIDGE_ADDRESS, GAS_TOKEN } from "../libs/constants";
 import useSequencerStore, { Claim } from "../store";
 
-const Bridge = () => {
+interface WithdrawProps {
+    baseUrl: string;
+}
+
+const Bridge = ({ baseUrl }: WithdrawProps) => {
     let { account, provider, chainId } = useWeb3React();
     const { batches } = useSequencerStore();
+    const [claims, setClaims] = useState<Claim[]>([]);
+    const [asset, setAsset] = useState(GAS_TOKEN);
+
+    // the sequencer only keeps the batch roots in its state, and generates proofs when asked
+    useEffect(() => {
+        if (!account) return;
+        fetch(`${baseUrl}/rpc?method=getWithdrawalClaims&address=${account}&asset=${asset}`)
+            .then((res) => res.json())
+            .then((body) => setClaims(body.claims || []))
+            .catch(console.error);
+    }, [account, asset, batches]);
 
     const withdraw = useCallback(
-        async (batchIndex: string, index: number, amount: string, proof: string[]) => {
+        async (batchIndex: string, index: number, asset: string, amount: string, proof: string[]) => {
             try {
                 if (!account || !provider || !chainId) {
                     window.alert('Ethereum wallet is not connected');
@@ -22,7 +37,7 @@ const Bridge = () => {
                 }
 
                 const rollup = new ethers.Contract(BRIDGE_ADDRESS[chainId], ROLLUP_ABI, provider.getSigner());
-                const receipt = await rollup.withdraw(batchIndex, index, account, amount, proof);
+                const receipt = await rollup.withdraw(batchIndex, index, account, asset, amount, proof);
 
                 console.log('receipt', receipt);
             } catch (err) {
@@ -34,25 +49,18 @@ const Bridge = () => {
 
     if (!account) return <></>
 
-    let accountClaims: Record<string, Claim> = {};
-    // TODO this is super inefficient
-    batches.forEach((batch, index) => {
-        Object.keys(batch.claims).forEach((key) => {
-            if (key === account!.toLowerCase()) {
-                accountClaims[index] = batch.claims[key];
-            }
-        });
-    })
-    console.log(accountClaims)
-    if (!accountClaims) return <></>
-
     return (
         <>
+            <input
+                type="text"
+                placeholder="token address (ETH if empty)"
+                onChange={(e) => setAsset(e.target.value || GAS_TOKEN)}
+            />
             {
-                Object.keys(accountClaims).map((key, i) => {
-                    const { index, amount, proof } = accountClaims[key];
+                claims.filter((claim) => claim.verified && claim.asset.toLowerCase() === asset.toLowerCase()).map((claim, i) => {
+                    const { batch, index, amount, proof } = claim;
                     return (
-                        <button key={i} onClick={(_) => withdraw(key, index, amount, proof)}>{`Withdraw ${amount} tokens`}</button>
+                        <button key={i} onClick={(_) => withdraw(batch.toString(), index, claim.asset, amount, proof)}>{`Withdraw ${amount} tokens`}</button>
                     )
                 })
             }
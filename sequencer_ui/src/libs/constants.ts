@@ -1,5 +1,9 @@
 import { Chain, CurrentConfig } from '../config'
 
+// Assets
+// the zero address stands for the gas token (ETH) on the rollup
+export const GAS_TOKEN = '0x0000000000000000000000000000000000000000'
+
 // Chains
 export const MAINNET_CHAIN_ID = 1
 export const OPTIMISM_CHAIN_ID = 10
@@ -0,0 +1,44 @@
+import { ethers } from 'ethers';
+import { Transaction } from './store';
+
+const STORAGE_KEY = 'sessionKey';
+// how long a session key stays valid once registered
+export const SESSION_KEY_DURATION_SECS = 60 * 60;
+
+type StoredSessionKey = {
+    account: string;
+    privateKey: string;
+    expiry: number;
+};
+
+// A fresh session key for `account`. It only gets used once `saveSessionKey` is called, after
+// the sequencer has accepted the RegisterSessionKey transaction for it.
+export function newSessionKey(): ethers.Wallet {
+    return ethers.Wallet.createRandom();
+}
+
+export function saveSessionKey(account: string, wallet: ethers.Wallet, expiry: number) {
+    const stored: StoredSessionKey = { account: account.toLowerCase(), privateKey: wallet.privateKey, expiry };
+    localStorage.setItem(STORAGE_KEY, JSON.stringify(stored));
+}
+
+// The session key registered for `account` from this browser, unless it has expired
+export function getSessionKey(account: string): ethers.Wallet | null {
+    const item = localStorage.getItem(STORAGE_KEY);
+    if (!item) return null;
+    const stored: StoredSessionKey = JSON.parse(item);
+    if (stored.account != account.toLowerCase() || stored.expiry <= Math.floor(Date.now() / 1000)) {
+        return null;
+    }
+    return new ethers.Wallet(stored.privateKey);
+}
+
+// Sign with the account's session key if there is one, so there is no wallet popup, and fall
+// back to the wallet otherwise. The sequencer rejects whatever is outside of the key's scope.
+export async function signTx(tx: Transaction, account: string, provider: ethers.providers.Web3Provider): Promise<string> {
+    const sessionKey = getSessionKey(account);
+    if (sessionKey) {
+        return sessionKey.signMessage(JSON.stringify(tx));
+    }
+    return provider.getSigner().signMessage(JSON.stringify(tx));
+}
@@ -5,6 +5,7 @@ export interface PendingGame {
   white: string;
   black: string;
   accepted: [boolean, boolean];
+  asset: string;
   wager: string;
 }
 
@@ -13,6 +14,7 @@ export interface Game {
   board: string;
   white: string;
   black: string;
+  asset: string;
   wager: string;
   status: string,
 }
@@ -21,7 +23,17 @@ export interface SignedTransaction {
   pub_key: string; // Converted camelCase for TypeScript conventions
   sig: Sig;
   tx: Transaction; // Still a hex string, but consider using ArrayBuffer or similar for binary data handling in JS/TS
-  // Additional fields like nonces, value, gas, gasPrice, gasLimit, etc., can be added as needed.
+  sponsorship?: Sponsorship;
+  // the other signers of a policy account, see AccountPolicy
+  cosigs?: Sig[];
+}
+
+// someone else paying the fees: `sponsor` personal_signs JSON.stringify({ sig, max_fee }),
+// with `sig` the user's signature as 0x-prefixed hex (r, s, v)
+export interface Sponsorship {
+  sponsor: string;
+  max_fee: string;
+  sig: Sig;
 }
 
 export type Sig = {
@@ -33,26 +45,105 @@ export type Sig = {
 export type Transaction = {
   data: TransactionData;
   nonce: string;
+  // optional deadline for sequencing the transaction, must come after nonce (the signed JSON is ordered)
+  valid_until?: ValidUntil;
+}
+
+export type ValidUntil = { Block: number } | { Timestamp: number };
+
+// what a session key may sign: only `actions`, only about `target` and moving at most `max_value`
+export type SessionScope = {
+  actions: string[];
+  target: string | null;
+  max_value: string | null;
+};
+
+// who signs for a policy account. Its signers personal_sign JSON.stringify({ account, tx })
+// instead of the tx alone, with `sig` from one of them and `cosigs` from the rest
+export type AccountPolicy =
+  | { Multisig: { owners: string[]; threshold: number } }
+  | { SpendingLimit: { owner: string; asset: string; daily_limit: string } }
+  | { Guardian: { owner: string; guardian: string; delay: number } };
 
+export interface PolicyAccount {
+  policy: AccountPolicy;
+  spent: string; // what it spent on `day`, under a SpendingLimit
+  day: number;
+  recovery: { owner: string; after: number } | null;
 }
 
 // For the `Transaction` enum, TypeScript uses a combination of types and interfaces to achieve similar functionality.
 export type TransactionData =
   | {
     Transfer: {
+      asset: string;
       from: string;
       to: string;
       amount: string; // BigNumber
     }
   }
   | {
-    WithdrawTokens: string; // BigNumber
+    Approve: {
+      asset: string;
+      spender: string;
+      amount: string; // BigNumber
+    }
+  }
+  | {
+    TransferFrom: {
+      asset: string;
+      from: string;
+      to: string;
+      amount: string; // BigNumber
+    }
+  }
+  | {
+    WithdrawTokens: {
+      asset: string;
+      amount: string; // BigNumber
+    }
+  }
+  | {
+    // several actions under one signature, all or nothing
+    Batch: TransactionData[];
+  }
+  | {
+    RegisterSessionKey: {
+      key: string;
+      expiry: ValidUntil;
+      scope: SessionScope;
+    }
+  }
+  | {
+    RevokeSessionKey: string;
+  }
+  | {
+    CreateAccount: {
+      policy: AccountPolicy;
+      salt: string;
+    }
+  }
+  | {
+    SetPolicy: AccountPolicy;
+  }
+  | {
+    StartRecovery: {
+      account: string;
+      owner: string;
+    }
   }
+  | "CancelRecovery"
+  | {
+    FinishRecovery: string;
+  }
+  // sent by the sequencer itself, like deposits
+  | "SealWithdrawals"
   | {
     Extension: | {
       ProposeGame: {
         white: string;
         black: string;
+        asset: string;
         wager: string; // BigNumber
       }
     }
@@ -70,12 +161,28 @@ export type TransactionData =
     }
   }
 
+export interface Receipt {
+  tx_hash: string;
+  block_number: number;
+  index: number;
+  status: boolean;
+  gas_used: number;
+  events: any[]; // TODO
+}
+
 export interface SequencerStore {
   sequenced: SignedTransaction[]
-  balances: Record<string, number> // TODO string?
+  balances: Record<string, Record<string, number>> // asset => account => balance TODO string?
   nonces: Record<string, number> // TODO string?
+  allowances: Record<string, Record<string, Record<string, string>>>
+  accounts?: Record<string, PolicyAccount>
   withdrawals: any, // TODO
   batches: Batch[], // TODO
+  fee_per_gas?: string
+  fee_recipient?: string
+  genesis_hash?: string
+  l1_chain_id?: number
+  bridge?: string
   state: {
     pending_games: Record<string, PendingGame>
     games: Record<string, Game>
@@ -84,13 +191,33 @@ export interface SequencerStore {
 }
 
 export interface Batch {
+  asset: string;
   root: string;
   token_total: string;
   num_drops: string;
-  claims: Record<string, Claim>
+  verified: boolean;
+  // only batches made before the sequencer kept their withdrawals
+  claims?: Record<string, { index: number; amount: string; proof: string[] }> | null;
 }
 
+export type WithdrawalStatus = "Pending" | "Batched" | "Verified" | "Claimed";
+
+// returned by the getWithdrawals rpc method: where the funds of each withdrawal are
+export interface WithdrawalRecord {
+  asset: string;
+  amount: string;
+  block: number;
+  batch: number | null;
+  index: number;
+  status: WithdrawalStatus;
+}
+
+// returned by the getWithdrawalClaims rpc method, which generates the proofs on demand
 export interface Claim {
+  batch: number;
+  asset: string;
+  root: string;
+  verified: boolean;
   amount: string;
   index: number;
   proof: string[];
@@ -102,6 +229,7 @@ const useSequencerStore = create<SequencerStore>()(
       sequenced: [],
       balances: {},
       nonces: {},
+      allowances: {},
       withdrawals: [],
       batches: [],
       state: {
@@ -1,5 +1,38 @@
 import { ethers } from 'ethers';
-import { Transaction, SignedTransaction } from './store';
+import { Transaction, SignedTransaction, Receipt } from './store';
+
+const POLL_INTERVAL_MS = 1000;
+
+// POST a signed transaction to the sequencer, then poll until the block it was sequenced into
+// has been sealed. Rejects with the sequencer's error message if the transaction was rejected.
+// A transaction with a future nonce is queued by the sequencer until the earlier ones arrive,
+// and rejects if it gets dropped from the queue instead.
+export async function submitTx(rpcUrl: string, wtx: SignedTransaction): Promise<Receipt> {
+    const res = await fetch(rpcUrl, {
+        method: "POST",
+        headers: {
+            "Content-Type": "application/json",
+        },
+        body: JSON.stringify(wtx),
+    });
+    const body = await res.json().catch(() => ({}));
+    if (!res.ok) {
+        throw new Error(body.message || `transaction rejected (${res.status})`);
+    }
+    const txHash: string = body.tx_hash;
+
+    while (true) {
+        const res = await fetch(`${rpcUrl}?method=getTransaction&hash=${txHash}`);
+        if (res.status === 404) {
+            throw new Error(`transaction ${txHash} was dropped`);
+        }
+        if (res.ok && (await res.json()).sealed) {
+            const receipt = await fetch(`${rpcUrl}?method=getTransactionReceipt&hash=${txHash}`);
+            return receipt.json();
+        }
+        await new Promise((resolve) => setTimeout(resolve, POLL_INTERVAL_MS));
+    }
+}
 
 export default async function sendTx(tx: Transaction, account: string, rpcUrl: string) {
     try {
@@ -22,15 +55,9 @@ export default async function sendTx(tx: Transaction, account: string, rpcUrl: s
             tx
         };
 
-        const receipt = await fetch(rpcUrl, {
-            method: "POST",
-            headers: {
-                "Content-Type": "application/json",
-            },
-            body: JSON.stringify(wtx),
-        });
+        const receipt = await submitTx(rpcUrl, wtx);
         console.log('receipt', receipt);
     } catch (err) {
         console.error(err);
     }
-}
\ No newline at end of file
+}